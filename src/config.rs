@@ -0,0 +1,348 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tracing::Level;
+
+/// How the MCP server talks to its clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// A single client over stdin/stdout (editors, CLIs).
+    Stdio,
+    /// Any number of clients over HTTP with server-sent events.
+    Sse,
+}
+
+/// How navigation requests are retried when rust-analyzer answers "content
+/// modified", which it does frequently while indexing.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub retries: u32,
+    /// Delay before the first retry, growing linearly with each attempt.
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            delay: Duration::from_millis(200),
+        }
+    }
+}
+
+/// The language server process to drive. Defaults to rust-analyzer.
+#[derive(Debug, Clone)]
+pub struct LanguageServerConfig {
+    pub command: String,
+    pub args: Vec<String>,
+    /// Sent as `initializationOptions` and used as the initial settings.
+    pub init_options: Value,
+    /// Language ids by file extension, consulted before the built-in table.
+    pub language_ids: HashMap<String, String>,
+}
+
+impl Default for LanguageServerConfig {
+    fn default() -> Self {
+        Self {
+            command: "rust-analyzer".to_string(),
+            args: Vec::new(),
+            init_options: json!({
+                "cargo": {
+                    "runBuildScripts": true,
+                    "features": "all"
+                }
+            }),
+            language_ids: HashMap::new(),
+        }
+    }
+}
+
+impl LanguageServerConfig {
+    /// LSP language identifier for a file, derived from its extension. Files
+    /// without a known extension are assumed to be Rust.
+    pub fn language_id<'a>(&'a self, path: &Path) -> &'a str {
+        let ext = path.extension().and_then(|ext| ext.to_str());
+        if let Some(id) = ext.and_then(|ext| self.language_ids.get(ext)) {
+            return id;
+        }
+
+        match ext {
+            Some("toml") => "toml",
+            Some("md") => "markdown",
+            Some("json") => "json",
+            Some("yaml" | "yml") => "yaml",
+            Some("c" | "h") => "c",
+            Some("cpp" | "cc" | "cxx" | "hpp") => "cpp",
+            Some("py") => "python",
+            Some("js") => "javascript",
+            Some("ts") => "typescript",
+            Some("go") => "go",
+            _ => "rust",
+        }
+    }
+}
+
+/// Command-line configuration for the MCP server.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Workspace folders registered with the language server. Never empty.
+    pub workspace_roots: Vec<PathBuf>,
+    pub transport: Transport,
+    /// Address the SSE transport listens on.
+    pub bind: SocketAddr,
+    /// Minimum level of log events; `RUST_LOG` directives can still refine it.
+    pub log_level: Level,
+    /// Append logs to this file instead of stderr. Logs never go to stdout,
+    /// which carries the MCP stdio transport.
+    pub log_file: Option<PathBuf>,
+    pub retry: RetryPolicy,
+    /// How long `diagnostics` waits for the server to publish diagnostics for a
+    /// file it has just opened; zero disables waiting.
+    pub diagnostics_wait: Duration,
+    /// Documents kept open on the server before the least recently used one is
+    /// closed; zero means no limit.
+    pub max_open_documents: usize,
+    /// Requests allowed in flight on the language server at once.
+    pub max_concurrent_requests: usize,
+    /// Shut the language server down after this long without requests and
+    /// start it again on the next one; `None` keeps it running.
+    pub idle_timeout: Option<Duration>,
+    /// Accept files outside every workspace root, which rust-analyzer only
+    /// analyzes in a limited detached mode. A warning is logged either way.
+    pub allow_external_files: bool,
+    /// Expose the `lsp_raw` tool, which sends arbitrary requests to the server.
+    pub enable_raw_lsp: bool,
+    pub server: LanguageServerConfig,
+}
+
+impl Config {
+    /// Parse `[--workspace <path>]... [--transport stdio|sse] [--bind <addr>]
+    /// [--log-level <level>] [--log-file <path>] [--retries <n>] [--retry-delay <ms>]
+    /// [--diagnostics-wait <ms>] [--max-open-documents <n>] [--max-concurrent-requests <n>]
+    /// [--idle-timeout <secs>] [--deny-external-files] [--enable-raw-lsp]
+    /// [--server-command <cmd>] [--server-arg <arg>]... [--init-options <json>]
+    /// [--language-id <ext>=<id>]... [<path>]...`.
+    /// Positional paths are treated as workspace roots too; with none given, the
+    /// current directory is used. Options also accept the `--option=value` form.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let cwd = std::env::current_dir()
+            .map_err(|e| format!("cannot determine current directory: {}", e))?;
+        let mut workspace_roots = Vec::new();
+        let mut transport = Transport::Stdio;
+        let mut bind: SocketAddr = ([127, 0, 0, 1], 8000).into();
+        let mut log_level = Level::INFO;
+        let mut log_file = None;
+        let mut retry = RetryPolicy::default();
+        let mut diagnostics_wait = Duration::from_secs(2);
+        let mut max_open_documents = 64;
+        let mut max_concurrent_requests = 8;
+        let mut idle_timeout = None;
+        let mut allow_external_files = true;
+        let mut enable_raw_lsp = false;
+        let mut server = LanguageServerConfig::default();
+        let mut init_options = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                workspace_roots.push(cwd.join(arg));
+                continue;
+            }
+
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} requires a value", name))
+            };
+
+            match name.as_str() {
+                "--workspace" => workspace_roots.push(cwd.join(value()?)),
+                "--transport" => {
+                    transport = match value()?.as_str() {
+                        "stdio" => Transport::Stdio,
+                        "sse" => Transport::Sse,
+                        other => return Err(format!("unknown transport: {}", other)),
+                    }
+                }
+                "--bind" => {
+                    let addr = value()?;
+                    bind = addr
+                        .parse()
+                        .map_err(|e| format!("invalid --bind address {}: {}", addr, e))?;
+                }
+                "--log-level" => {
+                    let level = value()?;
+                    log_level = level
+                        .parse()
+                        .map_err(|_| format!("invalid --log-level: {}", level))?;
+                }
+                "--log-file" => log_file = Some(cwd.join(value()?)),
+                "--retries" => {
+                    let retries = value()?;
+                    retry.retries = retries
+                        .parse()
+                        .map_err(|e| format!("invalid --retries {}: {}", retries, e))?;
+                }
+                "--retry-delay" => {
+                    let millis = value()?;
+                    retry.delay = millis
+                        .parse()
+                        .map(Duration::from_millis)
+                        .map_err(|e| format!("invalid --retry-delay {}: {}", millis, e))?;
+                }
+                "--diagnostics-wait" => {
+                    let millis = value()?;
+                    diagnostics_wait = millis
+                        .parse()
+                        .map(Duration::from_millis)
+                        .map_err(|e| format!("invalid --diagnostics-wait {}: {}", millis, e))?;
+                }
+                "--max-open-documents" => {
+                    let max = value()?;
+                    max_open_documents = max
+                        .parse()
+                        .map_err(|e| format!("invalid --max-open-documents {}: {}", max, e))?;
+                }
+                "--max-concurrent-requests" => {
+                    let max = value()?;
+                    max_concurrent_requests = match max.parse() {
+                        Ok(0) => {
+                            return Err("--max-concurrent-requests must be at least 1".to_string())
+                        }
+                        Ok(max) => max,
+                        Err(e) => {
+                            return Err(format!("invalid --max-concurrent-requests {}: {}", max, e))
+                        }
+                    };
+                }
+                "--idle-timeout" => {
+                    let secs = value()?;
+                    idle_timeout = secs
+                        .parse()
+                        .map(|secs| Some(Duration::from_secs(secs)).filter(|d| !d.is_zero()))
+                        .map_err(|e| format!("invalid --idle-timeout {}: {}", secs, e))?;
+                }
+                "--deny-external-files" => allow_external_files = false,
+                "--enable-raw-lsp" => enable_raw_lsp = true,
+                "--server-command" => server.command = value()?,
+                "--server-arg" => server.args.push(value()?),
+                "--init-options" => {
+                    let options = value()?;
+                    init_options = Some(
+                        serde_json::from_str(&options)
+                            .map_err(|e| format!("invalid --init-options {}: {}", options, e))?,
+                    );
+                }
+                "--language-id" => {
+                    let mapping = value()?;
+                    let (ext, id) = mapping.split_once('=').ok_or_else(|| {
+                        format!("--language-id expects <ext>=<id>, got {}", mapping)
+                    })?;
+                    server
+                        .language_ids
+                        .insert(ext.trim_start_matches('.').to_string(), id.to_string());
+                }
+                _ => return Err(format!("unknown option: {}", name)),
+            }
+        }
+
+        // rust-analyzer's options mean nothing to other servers
+        match init_options {
+            Some(options) => server.init_options = options,
+            None if !is_rust_analyzer_command(&server.command) => server.init_options = Value::Null,
+            None => {}
+        }
+
+        if workspace_roots.is_empty() {
+            workspace_roots.push(cwd);
+        }
+        // Resolved once so containment checks compare like with like
+        let workspace_roots = workspace_roots
+            .iter()
+            .map(|root| canonical_path(root))
+            .collect();
+
+        Ok(Self {
+            workspace_roots,
+            transport,
+            bind,
+            log_level,
+            log_file,
+            retry,
+            diagnostics_wait,
+            max_open_documents,
+            max_concurrent_requests,
+            idle_timeout,
+            allow_external_files,
+            enable_raw_lsp,
+            server,
+        })
+    }
+}
+
+/// Whether `command` runs rust-analyzer, wherever it is installed.
+pub fn is_rust_analyzer_command(command: &str) -> bool {
+    Path::new(command)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        == Some("rust-analyzer")
+}
+
+/// `path` with symlinks, `.` and `..` resolved. A path that doesn't exist yet
+/// is resolved lexically instead.
+pub fn canonical_path(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+/// Whether `path` lies under one of `roots`, which must already be canonical.
+pub fn is_in_workspace(roots: &[PathBuf], path: &Path) -> bool {
+    let path = canonical_path(path);
+    roots.iter().any(|root| path.starts_with(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(args: &[&str]) -> LanguageServerConfig {
+        Config::from_args(args.iter().map(|arg| arg.to_string()))
+            .unwrap()
+            .server
+    }
+
+    #[test]
+    fn rust_analyzer_options_follow_the_command_name() {
+        let default_options = LanguageServerConfig::default().init_options;
+        assert_eq!(server(&[]).init_options, default_options);
+        assert_eq!(
+            server(&["--server-command", "/usr/bin/rust-analyzer"]).init_options,
+            default_options
+        );
+        assert_eq!(
+            server(&["--server-command", "pyright-langserver"]).init_options,
+            Value::Null
+        );
+    }
+}
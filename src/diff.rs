@@ -0,0 +1,238 @@
+/// Lines of context kept around each change in a hunk.
+const CONTEXT_LINES: usize = 3;
+
+/// One step of a line edit script: an old line kept or deleted, or a new line
+/// inserted.
+#[derive(Clone, Copy)]
+enum Op {
+    Equal(usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// A unified diff from `old` to `new`, with `path` in the file headers. Empty
+/// when the texts are the same.
+pub fn unified(path: &str, old: &str, new: &str) -> String {
+    // Lines keep their terminators, so a missing final newline is a change
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = line_ops(&old_lines, &new_lines);
+
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Changes closer than twice the context share a hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + 1 + CONTEXT_LINES).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    // Old and new lines consumed before each op
+    let mut positions = Vec::with_capacity(ops.len());
+    let (mut old_line, mut new_line) = (0, 0);
+    for op in &ops {
+        positions.push((old_line, new_line));
+        match op {
+            Op::Equal(..) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            Op::Delete(_) => old_line += 1,
+            Op::Insert(_) => new_line += 1,
+        }
+    }
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path, path);
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let hunk = &ops[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, Op::Insert(_)))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, Op::Delete(_)))
+            .count();
+
+        // An empty side is numbered by the line before it, as diff does
+        let header_start = |start: usize, count: usize| if count == 0 { start } else { start + 1 };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            header_start(old_start, old_count),
+            old_count,
+            header_start(new_start, new_count),
+            new_count
+        ));
+        for op in hunk {
+            let (marker, line) = match *op {
+                Op::Equal(o) => (' ', old_lines[o]),
+                Op::Delete(o) => ('-', old_lines[o]),
+                Op::Insert(n) => ('+', new_lines[n]),
+            };
+            out.push(marker);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+/// The shortest edit script turning `old` into `new` (Myers' algorithm).
+fn line_ops(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    let offset = max as usize;
+    let mut v = vec![0isize; 2 * offset + 2];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + max) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk the furthest-reaching paths back from the end
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let previous_k =
+            if k == -d || (k != d && v[(k - 1 + max) as usize] < v[(k + 1 + max) as usize]) {
+                k + 1
+            } else {
+                k - 1
+            };
+        let previous_x = v[(previous_k + max) as usize];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            ops.push(Op::Equal(x as usize));
+        }
+        if d > 0 {
+            if x == previous_x {
+                ops.push(Op::Insert((y - 1) as usize));
+            } else {
+                ops.push(Op::Delete((x - 1) as usize));
+            }
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    ops.reverse();
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "--- a/f.rs\n+++ b/f.rs\n";
+
+    fn diff(old: &str, new: &str) -> String {
+        unified("f.rs", old, new)
+    }
+
+    #[test]
+    fn identical_texts_have_no_hunks() {
+        assert_eq!(diff("", ""), "");
+        assert_eq!(diff("a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn pure_insert_and_delete() {
+        assert_eq!(
+            diff("", "a\nb\n"),
+            format!("{}@@ -0,0 +1,2 @@\n+a\n+b\n", HEADER)
+        );
+        assert_eq!(
+            diff("a\nb\n", ""),
+            format!("{}@@ -1,2 +0,0 @@\n-a\n-b\n", HEADER)
+        );
+        assert_eq!(
+            diff("a\nc\n", "a\nb\nc\n"),
+            format!("{}@@ -1,2 +1,3 @@\n a\n+b\n c\n", HEADER)
+        );
+    }
+
+    #[test]
+    fn changes_at_the_start_and_end() {
+        assert_eq!(
+            diff("a\nb\nc\n", "x\nb\nc\n"),
+            format!("{}@@ -1,3 +1,3 @@\n-a\n+x\n b\n c\n", HEADER)
+        );
+        assert_eq!(
+            diff("a\nb\nc\n", "a\nb\nx\n"),
+            format!("{}@@ -1,3 +1,3 @@\n a\n b\n-c\n+x\n", HEADER)
+        );
+    }
+
+    #[test]
+    fn missing_trailing_newline_is_marked() {
+        assert_eq!(
+            diff("a\nb", "a\nb\n"),
+            format!(
+                "{}@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n",
+                HEADER
+            )
+        );
+        assert_eq!(
+            diff("a\nb", "a\nc"),
+            format!(
+                "{}@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n",
+                HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn distant_changes_get_separate_hunks() {
+        let old: String = (1..=20).map(|i| format!("{}\n", i)).collect();
+        let new: String = (1..=20)
+            .map(|i| match i {
+                2 => "two\n".to_string(),
+                18 => "eighteen\n".to_string(),
+                i => format!("{}\n", i),
+            })
+            .collect();
+        assert_eq!(
+            diff(&old, &new),
+            format!(
+                "{}@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n\
+                 @@ -15,6 +15,6 @@\n 15\n 16\n 17\n-18\n+eighteen\n 19\n 20\n",
+                HEADER
+            )
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use serde_json::Value;
+use std::fmt;
+
+use crate::config::is_rust_analyzer_command;
+
+/// JSON-RPC "internal error", used when the server's error object is malformed.
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// A JSON-RPC error object returned by the language server.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ResponseError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default)]
+    pub data: Option<Value>,
+}
+
+impl ResponseError {
+    /// The document changed while the request was being served, so retrying may succeed.
+    pub fn is_content_modified(&self) -> bool {
+        self.code == lsp_types::error_codes::CONTENT_MODIFIED
+    }
+
+    /// Parse an `error` member, keeping the raw value as the message if it is malformed.
+    pub fn from_value(error: &Value) -> Self {
+        serde_json::from_value(error.clone()).unwrap_or_else(|_| ResponseError {
+            code: INTERNAL_ERROR,
+            message: error.to_string(),
+            data: None,
+        })
+    }
+}
+
+/// Errors produced while talking to the language server.
+#[derive(Debug)]
+pub enum LspError {
+    /// I/O failure on the server pipes or while reading a source file.
+    Io(std::io::Error),
+    /// The language server process could not be started.
+    Spawn(std::io::Error),
+    /// The server did not answer `method` in time.
+    Timeout { method: String },
+    /// The server closed its output stream.
+    ServerExited,
+    /// The server sent a message that violates the base protocol framing.
+    InvalidFrame(String),
+    /// The server answered `method` with a JSON-RPC error.
+    Protocol {
+        method: String,
+        error: ResponseError,
+    },
+    /// A message could not be (de)serialized.
+    Json(serde_json::Error),
+    /// The server answered `method` with a result of the wrong shape.
+    UnexpectedResult {
+        method: String,
+        error: serde_json::Error,
+    },
+    /// A file path could not be converted to a `file://` URI.
+    BadPath(String),
+    /// A file lies outside every workspace folder and external files are not
+    /// allowed.
+    OutsideWorkspace(String),
+    /// The language server started with `command` did not complete the
+    /// `initialize` handshake.
+    Handshake {
+        command: String,
+        error: Box<LspError>,
+    },
+}
+
+impl fmt::Display for LspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LspError::Io(e) => write!(f, "I/O error: {}", e),
+            LspError::Spawn(e) => write!(f, "Failed to start language server: {}", e),
+            LspError::Timeout { method } => write!(f, "Timed out waiting for {}", method),
+            LspError::ServerExited => write!(f, "Language server exited"),
+            LspError::InvalidFrame(reason) => write!(f, "Invalid LSP message frame: {}", reason),
+            LspError::Protocol { method, error } => {
+                write!(f, "{} failed ({}): {}", method, error.code, error.message)
+            }
+            LspError::Json(e) => write!(f, "JSON error: {}", e),
+            LspError::UnexpectedResult { method, error } => {
+                write!(f, "{} returned an unexpected result: {}", method, error)
+            }
+            LspError::BadPath(path) => write!(f, "Invalid file path: {}", path),
+            LspError::OutsideWorkspace(path) => write!(
+                f,
+                "{} is outside the workspace; add its folder with --workspace or drop \
+                 --deny-external-files",
+                path
+            ),
+            LspError::Handshake { command, error } => {
+                write!(f, "{} failed to initialize: {}. ", command, error)?;
+                // The cargo hints only make sense for rust-analyzer
+                if is_rust_analyzer_command(command) {
+                    write!(
+                        f,
+                        "Check that `{} --version` works and that `cargo metadata` succeeds in \
+                         the workspace; setting RA_LOG=info and running rust-analyzer by hand \
+                         shows what it is stuck on",
+                        command
+                    )
+                } else {
+                    write!(
+                        f,
+                        "Check that `{}` starts a language server on stdio when run by hand",
+                        command
+                    )
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for LspError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LspError::Io(e) | LspError::Spawn(e) => Some(e),
+            LspError::Json(e) | LspError::UnexpectedResult { error: e, .. } => Some(e),
+            LspError::Handshake { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LspError {
+    fn from(e: std::io::Error) -> Self {
+        LspError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for LspError {
+    fn from(e: serde_json::Error) -> Self {
+        LspError::Json(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(command: &str) -> String {
+        LspError::Handshake {
+            command: command.to_string(),
+            error: Box::new(LspError::ServerExited),
+        }
+        .to_string()
+    }
+
+    #[test]
+    fn handshake_errors_name_the_configured_command() {
+        let message = handshake("/opt/ra/rust-analyzer");
+        assert!(message
+            .starts_with("/opt/ra/rust-analyzer failed to initialize: Language server exited."));
+        assert!(message.contains("`/opt/ra/rust-analyzer --version`"));
+        assert!(message.contains("cargo metadata"));
+
+        let message = handshake("pyright-langserver");
+        assert!(message.starts_with("pyright-langserver failed to initialize"));
+        assert!(!message.contains("cargo"));
+        assert!(!message.contains("rust-analyzer"));
+    }
+}
@@ -1,53 +1,239 @@
 use lsp_types::{request::GotoImplementationParams, *};
 use serde_json::{json, Value};
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
 use tokio::process::{Child, Command};
-use tokio::sync::Mutex;
-use tracing::{debug, info};
+use tokio::sync::{oneshot, Mutex, Notify, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::{self, LanguageServerConfig, RetryPolicy};
+use crate::error::{LspError, ResponseError};
+use crate::position::position_at;
+
+/// How long a synced document is trusted without checking the file on disk,
+/// so rapid successive tool calls on one file don't resync it.
+const DOCUMENT_SYNC_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How long rust-analyzer may take to answer `initialize` before startup fails.
+const INITIALIZE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long each step of a clean shutdown may take before the server is killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where messages for the server are written: its stdin, or a pipe in tests.
+type ServerInput = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Where the server's messages are read from.
+type ServerOutput = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Senders for the requests still awaiting a response, by request id.
+type PendingRequests = Arc<Mutex<HashMap<i64, oneshot::Sender<Value>>>>;
+
+/// Sync state of a document opened with `textDocument/didOpen`.
+struct OpenDocument {
+    version: i32,
+    /// Modification time of the file when its text was last sent.
+    modified: SystemTime,
+    /// The text last sent, which incremental changes are computed against.
+    text: String,
+    /// When `didOpen` was sent, before which the server had never seen it.
+    opened_at: Instant,
+    synced_at: Instant,
+    /// When a tool last used the document, for least-recently-used eviction.
+    last_used: Instant,
+}
+
+/// The latest state of one piece of server work reported with `$/progress`,
+/// such as rust-analyzer's indexing or build script runs.
+#[derive(Debug, Clone)]
+pub struct WorkProgress {
+    pub title: String,
+    pub message: Option<String>,
+    pub percentage: Option<u32>,
+}
 
 pub struct LspClient {
-    process: Child,
-    stdin: Mutex<tokio::process::ChildStdin>,
-    stdout: Mutex<BufReader<tokio::process::ChildStdout>>,
-    request_id: Mutex<i64>,
-    workspace_root: PathBuf,
+    /// `None` when the server isn't a child process, as in tests.
+    process: Option<Child>,
+    stdin: Arc<Mutex<ServerInput>>,
+    /// Reads the server's output for as long as it runs, handing responses to
+    /// the requests in `pending`.
+    reader: JoinHandle<()>,
+    request_id: AtomicI64,
+    pending: PendingRequests,
+    /// Set once the server's output has closed.
+    server_exited: Arc<AtomicBool>,
+    /// Woken whenever the server publishes diagnostics.
+    diagnostics_published: Arc<Notify>,
+    workspace_roots: Vec<PathBuf>,
     is_ready: Arc<AtomicBool>,
-    opened_documents: Mutex<HashSet<String>>,
+    opened_documents: Mutex<HashMap<String, OpenDocument>>,
+    /// File text by resolved path, with the modification time it was read at.
+    file_contents: Mutex<HashMap<String, (SystemTime, String)>>,
+    sync_hits: AtomicU64,
+    sync_misses: AtomicU64,
+    server_capabilities: ServerCapabilities,
+    server_info: Option<ServerInfo>,
+    retry: RetryPolicy,
+    diagnostics_wait: Duration,
+    /// Open documents allowed before the least recently used is closed; zero
+    /// means no limit.
+    max_open_documents: usize,
+    /// Permits for requests in flight on the server, so a burst of tool calls
+    /// can't overwhelm it.
+    request_permits: Semaphore,
+    allow_external_files: bool,
+    /// Latest diagnostics pushed with `textDocument/publishDiagnostics`.
+    published_diagnostics: Arc<Mutex<HashMap<Url, Vec<Diagnostic>>>>,
+    /// Last full `textDocument/diagnostic` report per document with its result
+    /// id, served again when the server answers "unchanged".
+    pulled_diagnostics: Mutex<HashMap<Url, FullDocumentDiagnosticReport>>,
+    /// Items streamed with `$/progress`, by partial result token.
+    partial_results: Arc<Mutex<HashMap<String, Vec<Value>>>>,
+    next_partial_token: AtomicU64,
+    /// Work in progress on the server, by progress token.
+    work_progress: Arc<Mutex<HashMap<String, WorkProgress>>>,
+    /// Server settings, sent as initialization options and served to
+    /// `workspace/configuration` requests.
+    settings: Arc<Mutex<Value>>,
+    server: LanguageServerConfig,
 }
 
 impl LspClient {
-    pub async fn new(workspace_root: &PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
-        info!("Starting rust-analyzer process");
+    pub async fn new(
+        workspace_roots: &[PathBuf],
+        server: &LanguageServerConfig,
+        retry: RetryPolicy,
+        diagnostics_wait: Duration,
+        max_open_documents: usize,
+        max_concurrent_requests: usize,
+        allow_external_files: bool,
+    ) -> Result<Self, LspError> {
+        info!(
+            "Starting language server: {} {:?}",
+            server.command, server.args
+        );
 
-        let mut process = Command::new("rust-analyzer")
+        let mut process = Command::new(&server.command)
+            .args(&server.args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::null())
-            .spawn()?;
+            .spawn()
+            .map_err(LspError::Spawn)?;
 
         let stdin = process.stdin.take().unwrap();
-        let stdout = BufReader::new(process.stdout.take().unwrap());
+        let stdout = process.stdout.take().unwrap();
+
+        let mut client = Self::connect(
+            Box::new(stdin),
+            Box::new(stdout),
+            Some(process),
+            workspace_roots,
+            server,
+        );
+        client.retry = retry;
+        client.diagnostics_wait = diagnostics_wait;
+        client.max_open_documents = max_open_documents;
+        client.request_permits = Semaphore::new(max_concurrent_requests);
+        client.allow_external_files = allow_external_files;
 
-        let mut client = Self {
-            process,
-            stdin: Mutex::new(stdin),
-            stdout: Mutex::new(stdout),
-            request_id: Mutex::new(0),
-            workspace_root: workspace_root.clone(),
-            is_ready: Arc::new(AtomicBool::new(false)),
-            opened_documents: Mutex::new(HashSet::new()),
+        // Initialize synchronously for now - we'll add async initialization later.
+        client.handshake().await?;
+
+        Ok(client)
+    }
+
+    /// A client for a server reached through `input` and `output`, not yet
+    /// initialized and without limits. Starts reading the server's output.
+    fn connect(
+        input: ServerInput,
+        output: ServerOutput,
+        process: Option<Child>,
+        workspace_roots: &[PathBuf],
+        server: &LanguageServerConfig,
+    ) -> Self {
+        let stdin = Arc::new(Mutex::new(input));
+        let pending: PendingRequests = Arc::new(Mutex::new(HashMap::new()));
+        let server_exited = Arc::new(AtomicBool::new(false));
+        let responder = Responder {
+            stdin: stdin.clone(),
+            settings: Arc::new(Mutex::new(server.init_options.clone())),
+            own_section: Path::new(&server.command)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            published_diagnostics: Arc::new(Mutex::new(HashMap::new())),
+            diagnostics_published: Arc::new(Notify::new()),
+            partial_results: Arc::new(Mutex::new(HashMap::new())),
+            work_progress: Arc::new(Mutex::new(HashMap::new())),
         };
 
-        // Initialize synchronously for now - we'll add async initialization later
-        client.initialize().await?;
-        client.is_ready.store(true, Ordering::Relaxed);
+        Self {
+            process,
+            stdin,
+            request_id: AtomicI64::new(0),
+            pending: pending.clone(),
+            server_exited: server_exited.clone(),
+            diagnostics_published: responder.diagnostics_published.clone(),
+            workspace_roots: workspace_roots.to_vec(),
+            is_ready: Arc::new(AtomicBool::new(false)),
+            opened_documents: Mutex::new(HashMap::new()),
+            file_contents: Mutex::new(HashMap::new()),
+            sync_hits: AtomicU64::new(0),
+            sync_misses: AtomicU64::new(0),
+            server_capabilities: ServerCapabilities::default(),
+            server_info: None,
+            retry: RetryPolicy::default(),
+            diagnostics_wait: Duration::ZERO,
+            max_open_documents: 0,
+            request_permits: Semaphore::new(Semaphore::MAX_PERMITS),
+            allow_external_files: false,
+            published_diagnostics: responder.published_diagnostics.clone(),
+            pulled_diagnostics: Mutex::new(HashMap::new()),
+            partial_results: responder.partial_results.clone(),
+            next_partial_token: AtomicU64::new(0),
+            work_progress: responder.work_progress.clone(),
+            settings: responder.settings.clone(),
+            server: server.clone(),
+            reader: tokio::spawn(Self::read_loop(
+                BufReader::new(output),
+                pending,
+                responder,
+                server_exited,
+            )),
+        }
+    }
 
-        Ok(client)
+    /// Initialize the server, killing it if that fails or takes too long.
+    async fn handshake(&mut self) -> Result<(), LspError> {
+        // rust-analyzer answers before indexing, so a missing reply means it is stuck
+        let handshake = match tokio::time::timeout(INITIALIZE_TIMEOUT, self.initialize()).await {
+            Ok(result) => result,
+            Err(_) => Err(LspError::Timeout {
+                method: "initialize".to_string(),
+            }),
+        };
+        if let Err(e) = handshake {
+            // Don't leave a hung or half-started server behind
+            if let Some(process) = &mut self.process {
+                let _ = process.start_kill();
+            }
+            return Err(LspError::Handshake {
+                command: self.server.command.clone(),
+                error: Box::new(e),
+            });
+        }
+        self.is_ready.store(true, Ordering::Relaxed);
+        Ok(())
     }
 
     pub async fn wait_for_ready(&self) {
@@ -56,30 +242,192 @@ impl LspClient {
         }
     }
 
-    pub fn is_ready(&self) -> bool {
-        self.is_ready.load(Ordering::Relaxed)
+    /// Resolve a relative path against the workspace folder that contains it,
+    /// falling back to the first folder. Absolute paths are returned unchanged.
+    pub fn resolve_path(&self, file_path: &str) -> String {
+        let path = Path::new(file_path);
+        if path.is_absolute() {
+            return file_path.to_string();
+        }
+
+        self.workspace_roots
+            .iter()
+            .map(|root| root.join(path))
+            .find(|candidate| candidate.exists())
+            .unwrap_or_else(|| self.workspace_roots[0].join(path))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn file_uri(&self, file_path: &str) -> Result<Url, LspError> {
+        let path = self.resolve_path(file_path);
+        if !self.allow_external_files && !self.is_in_workspace(Path::new(&path)) {
+            return Err(LspError::OutsideWorkspace(path));
+        }
+        Url::from_file_path(path).map_err(|_| LspError::BadPath(file_path.to_string()))
+    }
+
+    /// Whether `path` lies under one of the workspace roots once `..` and
+    /// symlinks are resolved.
+    fn is_in_workspace(&self, path: &Path) -> bool {
+        config::is_in_workspace(&self.workspace_roots, path)
+    }
+
+    pub fn server_capabilities(&self) -> &ServerCapabilities {
+        &self.server_capabilities
+    }
+
+    /// Command ids the server advertised for `workspace/executeCommand`.
+    pub fn commands(&self) -> &[String] {
+        self.server_capabilities
+            .execute_command_provider
+            .as_ref()
+            .map_or(&[], |options| options.commands.as_slice())
+    }
+
+    pub fn server_info(&self) -> Option<&ServerInfo> {
+        self.server_info.as_ref()
+    }
+
+    /// Whether the backend is rust-analyzer, which the `rust-analyzer/*`
+    /// extension requests require.
+    pub fn is_rust_analyzer(&self) -> bool {
+        self.server_info
+            .as_ref()
+            .is_some_and(|info| info.name == "rust-analyzer")
+    }
+
+    pub fn supports_incremental_sync(&self) -> bool {
+        let kind = match &self.server_capabilities.text_document_sync {
+            Some(TextDocumentSyncCapability::Kind(kind)) => Some(*kind),
+            Some(TextDocumentSyncCapability::Options(options)) => options.change,
+            None => None,
+        };
+        kind == Some(TextDocumentSyncKind::INCREMENTAL)
+    }
+
+    pub fn supports_range_formatting(&self) -> bool {
+        match &self.server_capabilities.document_range_formatting_provider {
+            Some(OneOf::Left(enabled)) => *enabled,
+            Some(OneOf::Right(_)) => true,
+            None => false,
+        }
+    }
+
+    pub fn supports_prepare_rename(&self) -> bool {
+        match &self.server_capabilities.rename_provider {
+            Some(OneOf::Right(options)) => options.prepare_provider.unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    pub fn supports_moniker(&self) -> bool {
+        match &self.server_capabilities.moniker_provider {
+            Some(OneOf::Left(enabled)) => *enabled,
+            Some(OneOf::Right(_)) => true,
+            None => false,
+        }
+    }
+
+    pub fn supports_document_link_resolve(&self) -> bool {
+        self.server_capabilities
+            .document_link_provider
+            .as_ref()
+            .and_then(|options| options.resolve_provider)
+            .unwrap_or(false)
+    }
+
+    pub fn supports_completion_resolve(&self) -> bool {
+        self.server_capabilities
+            .completion_provider
+            .as_ref()
+            .and_then(|options| options.resolve_provider)
+            .unwrap_or(false)
     }
 
-    async fn initialize(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let workspace_folder = WorkspaceFolder {
-            uri: Url::from_file_path(&self.workspace_root).unwrap(),
-            name: self
-                .workspace_root
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("workspace")
-                .to_string(),
+    pub fn supports_code_action_resolve(&self) -> bool {
+        match &self.server_capabilities.code_action_provider {
+            Some(CodeActionProviderCapability::Options(options)) => {
+                options.resolve_provider.unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn supports_code_lens_resolve(&self) -> bool {
+        self.server_capabilities
+            .code_lens_provider
+            .as_ref()
+            .and_then(|options| options.resolve_provider)
+            .unwrap_or(false)
+    }
+
+    pub fn supports_inlay_hint_resolve(&self) -> bool {
+        let options = match &self.server_capabilities.inlay_hint_provider {
+            Some(OneOf::Right(InlayHintServerCapabilities::Options(options))) => options,
+            Some(OneOf::Right(InlayHintServerCapabilities::RegistrationOptions(registration))) => {
+                &registration.inlay_hint_options
+            }
+            _ => return false,
         };
+        options.resolve_provider.unwrap_or(false)
+    }
+
+    async fn initialize(&mut self) -> Result<(), LspError> {
+        let workspace_folders = self
+            .workspace_roots
+            .iter()
+            .map(|root| {
+                Ok(WorkspaceFolder {
+                    uri: Url::from_file_path(root)
+                        .map_err(|_| LspError::BadPath(root.display().to_string()))?,
+                    name: root
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("workspace")
+                        .to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, LspError>>()?;
 
         let initialize_params = InitializeParams {
-            capabilities: ClientCapabilities::default(),
-            workspace_folders: Some(vec![workspace_folder]),
-            initialization_options: Some(json!({
-                "cargo": {
-                    "runBuildScripts": true,
-                    "features": "all"
-                }
-            })),
+            capabilities: ClientCapabilities {
+                // rust-analyzer only emits run/debug/reference lenses for commands the client handles
+                experimental: Some(json!({
+                    "commands": {
+                        "commands": [
+                            "rust-analyzer.runSingle",
+                            "rust-analyzer.debugSingle",
+                            "rust-analyzer.showReferences",
+                            "rust-analyzer.gotoLocation"
+                        ]
+                    }
+                })),
+                // Snippet completions carry placeholders for each argument of a call
+                text_document: Some(TextDocumentClientCapabilities {
+                    completion: Some(CompletionClientCapabilities {
+                        completion_item: Some(CompletionItemCapability {
+                            snippet_support: Some(true),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                // Settings changed at runtime are pulled with workspace/configuration
+                workspace: Some(WorkspaceClientCapabilities {
+                    configuration: Some(true),
+                    ..Default::default()
+                }),
+                // Lets the server report indexing and build progress
+                window: Some(WindowClientCapabilities {
+                    work_done_progress: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            workspace_folders: Some(workspace_folders),
+            initialization_options: Some(self.settings.lock().await.clone()),
             ..Default::default()
         };
 
@@ -88,74 +436,230 @@ impl LspClient {
             "LSP initialized with capabilities: {:?}",
             response.capabilities
         );
+        self.server_capabilities = response.capabilities;
+        self.server_info = response.server_info;
 
         self.notify("initialized", InitializedParams {}).await?;
 
         Ok(())
     }
 
-    pub async fn open_document(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // Check if document is already opened
-        {
-            let opened_docs = self.opened_documents.lock().await;
-            if opened_docs.contains(file_path) {
-                debug!("Document already opened, using cache: {}", file_path);
+    pub async fn open_document(&self, file_path: &str) -> Result<(), LspError> {
+        let file_path = &self.resolve_path(file_path);
+
+        // Hold the lock for the whole sync so concurrent callers share one read
+        let mut opened_docs = self.opened_documents.lock().await;
+
+        if let Some(doc) = opened_docs.get_mut(file_path) {
+            doc.last_used = Instant::now();
+            // Back-to-back operations on one file skip even the mtime check
+            if doc.synced_at.elapsed() < DOCUMENT_SYNC_DEBOUNCE {
+                self.record_sync_hit(file_path);
                 return Ok(());
             }
+
+            let modified = tokio::fs::metadata(file_path).await?.modified()?;
+            if modified == doc.modified {
+                doc.synced_at = Instant::now();
+                self.record_sync_hit(file_path);
+                return Ok(());
+            }
+
+            // File changed on disk since it was last sent, resync it
+            self.record_sync_miss(file_path);
+            let content = self.read_file_modified_at(file_path, modified).await?;
+            return self.send_change(file_path, doc, modified, content).await;
         }
 
-        // Document not opened yet, open it
-        debug!("Opening new document: {}", file_path);
-        let content = tokio::fs::read_to_string(file_path).await?;
+        // Document not opened yet, make room for it first
+        if self.max_open_documents > 0 && opened_docs.len() >= self.max_open_documents {
+            let least_recent = opened_docs
+                .iter()
+                .min_by_key(|(_, doc)| doc.last_used)
+                .map(|(path, _)| path.clone());
+            if let Some(path) = least_recent {
+                debug!("Open document limit reached, closing {}", path);
+                self.send_did_close(&path).await?;
+                opened_docs.remove(&path);
+            }
+        }
+
+        self.record_sync_miss(file_path);
+        let uri = self.file_uri(file_path)?;
+        if !self.is_in_workspace(Path::new(file_path)) {
+            warn!(
+                "{} is outside the workspace; rust-analyzer analyzes it as a detached file, so results may be limited",
+                file_path
+            );
+        }
+        let modified = tokio::fs::metadata(file_path).await?.modified()?;
+        let content = self.read_file_modified_at(file_path, modified).await?;
+        // Anything published before a reopen describes text that may be gone
+        self.published_diagnostics.lock().await.remove(&uri);
         let params = DidOpenTextDocumentParams {
             text_document: TextDocumentItem {
-                uri: Url::from_file_path(file_path).unwrap(),
-                language_id: "rust".to_string(),
+                uri,
+                language_id: self.server.language_id(Path::new(file_path)).to_string(),
                 version: 1,
-                text: content,
+                text: content.clone(),
             },
         };
 
         self.notify("textDocument/didOpen", params).await?;
 
         // Mark document as opened
-        {
-            let mut opened_docs = self.opened_documents.lock().await;
-            opened_docs.insert(file_path.to_string());
-            debug!("Document opened and cached. Total opened documents: {}", opened_docs.len());
-        }
+        opened_docs.insert(
+            file_path.to_string(),
+            OpenDocument {
+                version: 1,
+                modified,
+                text: content,
+                opened_at: Instant::now(),
+                synced_at: Instant::now(),
+                last_used: Instant::now(),
+            },
+        );
+        debug!(
+            "Document opened and cached. Total opened documents: {}",
+            opened_docs.len()
+        );
+
+        Ok(())
+    }
+
+    /// Send an open document's new text with `didChange`, as a ranged change
+    /// when the server supports incremental sync.
+    async fn send_change(
+        &self,
+        file_path: &str,
+        doc: &mut OpenDocument,
+        modified: SystemTime,
+        content: String,
+    ) -> Result<(), LspError> {
+        let change = if self.supports_incremental_sync() {
+            incremental_change(&doc.text, &content)
+        } else {
+            None
+        };
+        let change = change.unwrap_or_else(|| TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: content.clone(),
+        });
+        doc.version += 1;
+        doc.modified = modified;
+        doc.text = content;
+        doc.synced_at = Instant::now();
+        let params = DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: self.file_uri(file_path)?,
+                version: doc.version,
+            },
+            content_changes: vec![change],
+        };
+        self.notify("textDocument/didChange", params).await
+    }
 
+    /// Write `text` to a file and, if it is open, sync it to the server right
+    /// away rather than on the next use, which the debounce could delay.
+    pub async fn write_file(&self, file_path: &str, text: &str) -> Result<(), LspError> {
+        let file_path = &self.resolve_path(file_path);
+        let mut opened_docs = self.opened_documents.lock().await;
+
+        tokio::fs::write(file_path, text).await?;
+        let modified = tokio::fs::metadata(file_path).await?.modified()?;
+        self.file_contents
+            .lock()
+            .await
+            .insert(file_path.to_string(), (modified, text.to_string()));
+
+        if let Some(doc) = opened_docs.get_mut(file_path) {
+            self.send_change(file_path, doc, modified, text.to_string())
+                .await?;
+        }
         Ok(())
     }
 
-    pub async fn close_document(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // Check if document is opened
-        {
-            let opened_docs = self.opened_documents.lock().await;
-            if !opened_docs.contains(file_path) {
-                debug!("Document not opened, no need to close: {}", file_path);
-                return Ok(()); // Already closed or never opened
+    /// Read a file's text, reusing the cached copy while its modification time
+    /// is unchanged so tools see the same text the server was sent.
+    pub async fn read_file(&self, file_path: &str) -> Result<String, LspError> {
+        let file_path = &self.resolve_path(file_path);
+        let modified = tokio::fs::metadata(file_path).await?.modified()?;
+        self.read_file_modified_at(file_path, modified).await
+    }
+
+    /// Read the file at a resolved path whose modification time is `modified`.
+    async fn read_file_modified_at(
+        &self,
+        file_path: &str,
+        modified: SystemTime,
+    ) -> Result<String, LspError> {
+        let mut contents = self.file_contents.lock().await;
+        if let Some((cached_modified, text)) = contents.get(file_path) {
+            if *cached_modified == modified {
+                debug!("File content cache hit: {}", file_path);
+                return Ok(text.clone());
             }
         }
 
-        // Close the document
-        debug!("Closing document: {}", file_path);
-        let params = DidCloseTextDocumentParams {
-            text_document: TextDocumentIdentifier {
-                uri: Url::from_file_path(file_path).unwrap(),
-            },
-        };
+        let text = tokio::fs::read_to_string(file_path).await?;
+        contents.insert(file_path.to_string(), (modified, text.clone()));
+        Ok(text)
+    }
 
-        self.notify("textDocument/didClose", params).await?;
+    fn record_sync_hit(&self, file_path: &str) {
+        let hits = self.sync_hits.fetch_add(1, Ordering::Relaxed) + 1;
+        debug!(
+            "Document sync cache hit for {} ({} hits, {} misses)",
+            file_path,
+            hits,
+            self.sync_misses.load(Ordering::Relaxed)
+        );
+    }
+
+    fn record_sync_miss(&self, file_path: &str) {
+        let misses = self.sync_misses.fetch_add(1, Ordering::Relaxed) + 1;
+        debug!(
+            "Document sync cache miss for {} ({} hits, {} misses)",
+            file_path,
+            self.sync_hits.load(Ordering::Relaxed),
+            misses
+        );
+    }
+
+    /// Send `textDocument/didClose` for an opened document. Returns `false` if
+    /// the document was not open.
+    pub async fn close_document(&self, file_path: &str) -> Result<bool, LspError> {
+        let file_path = &self.resolve_path(file_path);
 
-        // Remove from opened documents tracking
-        {
-            let mut opened_docs = self.opened_documents.lock().await;
-            opened_docs.remove(file_path);
-            debug!("Document closed. Total opened documents: {}", opened_docs.len());
+        // Hold the lock throughout so a concurrent open can't be closed by mistake
+        let mut opened_docs = self.opened_documents.lock().await;
+        if !opened_docs.contains_key(file_path) {
+            debug!("Document not opened, no need to close: {}", file_path);
+            return Ok(false); // Already closed or never opened
         }
 
-        Ok(())
+        // Close the document
+        debug!("Closing document: {}", file_path);
+        self.send_did_close(file_path).await?;
+        opened_docs.remove(file_path);
+        debug!(
+            "Document closed. Total opened documents: {}",
+            opened_docs.len()
+        );
+
+        Ok(true)
+    }
+
+    async fn send_did_close(&self, file_path: &str) -> Result<(), LspError> {
+        let uri = self.file_uri(file_path)?;
+        // Diagnostics for a closed document go stale
+        self.published_diagnostics.lock().await.remove(&uri);
+        self.pulled_diagnostics.lock().await.remove(&uri);
+        let params = DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri },
+        };
+        self.notify("textDocument/didClose", params).await
     }
 
     pub async fn get_opened_documents_count(&self) -> usize {
@@ -163,19 +667,40 @@ impl LspClient {
         opened_docs.len()
     }
 
+    /// Server work currently in progress, ordered by title.
+    pub async fn work_progress(&self) -> Vec<WorkProgress> {
+        let mut progress: Vec<WorkProgress> =
+            self.work_progress.lock().await.values().cloned().collect();
+        progress.sort_by(|a, b| a.title.cmp(&b.title));
+        progress
+    }
+
+    /// The open document limit; zero means no limit.
+    pub fn max_open_documents(&self) -> usize {
+        self.max_open_documents
+    }
+
+    /// Document sync cache (hits, misses) so far.
+    pub fn sync_stats(&self) -> (u64, u64) {
+        (
+            self.sync_hits.load(Ordering::Relaxed),
+            self.sync_misses.load(Ordering::Relaxed),
+        )
+    }
+
     pub async fn hover(
         &self,
         file_path: &str,
         line: u32,
         column: u32,
-    ) -> Result<Option<Hover>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<Hover>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = HoverParams {
             text_document_position_params: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: Url::from_file_path(file_path).unwrap(),
+                    uri: self.file_uri(file_path)?,
                 },
                 position: Position {
                     line,
@@ -185,7 +710,7 @@ impl LspClient {
             work_done_progress_params: WorkDoneProgressParams::default(),
         };
 
-        self.request("textDocument/hover", params).await
+        self.request_with_retry("textDocument/hover", params).await
     }
 
     pub async fn completion(
@@ -193,14 +718,31 @@ impl LspClient {
         file_path: &str,
         line: u32,
         column: u32,
-    ) -> Result<Option<CompletionResponse>, Box<dyn std::error::Error>> {
+        trigger_kind: Option<CompletionTriggerKind>,
+        trigger_character: Option<String>,
+    ) -> Result<Option<CompletionResponse>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
+
+        // Without trigger information let the server treat this as a manual invocation
+        let context = if trigger_kind.is_none() && trigger_character.is_none() {
+            None
+        } else {
+            Some(CompletionContext {
+                trigger_kind: trigger_kind.unwrap_or(if trigger_character.is_some() {
+                    CompletionTriggerKind::TRIGGER_CHARACTER
+                } else {
+                    CompletionTriggerKind::INVOKED
+                }),
+                trigger_character,
+            })
+        };
+
         let params = CompletionParams {
             text_document_position: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: Url::from_file_path(file_path).unwrap(),
+                    uri: self.file_uri(file_path)?,
                 },
                 position: Position {
                     line,
@@ -209,40 +751,74 @@ impl LspClient {
             },
             work_done_progress_params: WorkDoneProgressParams::default(),
             partial_result_params: PartialResultParams::default(),
-            context: None,
+            context,
         };
 
         self.request("textDocument/completion", params).await
     }
 
-    pub async fn diagnostics(
-        &self,
-        file_path: &str,
-    ) -> Result<Vec<Diagnostic>, Box<dyn std::error::Error>> {
+    pub async fn diagnostics(&self, file_path: &str) -> Result<Vec<Diagnostic>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
+
+        // The server hasn't analyzed a file it has only just seen, so pulling
+        // right away comes back empty; give it a moment to publish instead
+        let opened_at = self
+            .opened_documents
+            .lock()
+            .await
+            .get(&self.resolve_path(file_path))
+            .map(|doc| doc.opened_at);
+        let remaining = opened_at
+            .and_then(|opened_at| self.diagnostics_wait.checked_sub(opened_at.elapsed()))
+            .filter(|remaining| !remaining.is_zero());
+        if let Some(remaining) = remaining {
+            let uri = self.file_uri(file_path)?;
+            if let Some(published) = self.wait_for_published_diagnostics(&uri, remaining).await? {
+                return Ok(published);
+            }
+        }
+
+        let uri = self.file_uri(file_path)?;
+        let previous_result_id = self
+            .pulled_diagnostics
+            .lock()
+            .await
+            .get(&uri)
+            .and_then(|report| report.result_id.clone());
         let params = DocumentDiagnosticParams {
-            text_document: TextDocumentIdentifier {
-                uri: Url::from_file_path(file_path).unwrap(),
-            },
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
             identifier: None,
-            previous_result_id: None,
+            previous_result_id,
             work_done_progress_params: WorkDoneProgressParams::default(),
             partial_result_params: PartialResultParams::default(),
         };
 
-        let response: DocumentDiagnosticReportResult =
+        let response: Option<DocumentDiagnosticReportResult> =
             self.request("textDocument/diagnostic", params).await?;
 
         match response {
-            DocumentDiagnosticReportResult::Report(report) => match report {
+            None => Ok(vec![]),
+            Some(DocumentDiagnosticReportResult::Report(report)) => match report {
                 DocumentDiagnosticReport::Full(full) => {
-                    Ok(full.full_document_diagnostic_report.items)
+                    let report = full.full_document_diagnostic_report;
+                    self.pulled_diagnostics
+                        .lock()
+                        .await
+                        .insert(uri, report.clone());
+                    Ok(report.items)
                 }
-                DocumentDiagnosticReport::Unchanged(_) => Ok(vec![]),
+                // "Unchanged" means the same as the report we last received
+                DocumentDiagnosticReport::Unchanged(_) => Ok(self
+                    .pulled_diagnostics
+                    .lock()
+                    .await
+                    .get(&uri)
+                    .map(|report| report.items.clone())
+                    .unwrap_or_default()),
             },
-            DocumentDiagnosticReportResult::Partial(_) => Ok(vec![]),
+            Some(DocumentDiagnosticReportResult::Partial(_)) => Ok(vec![]),
         }
     }
 
@@ -251,14 +827,14 @@ impl LspClient {
         file_path: &str,
         line: u32,
         column: u32,
-    ) -> Result<Option<GotoDefinitionResponse>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<GotoDefinitionResponse>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = GotoDefinitionParams {
             text_document_position_params: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: Url::from_file_path(file_path).unwrap(),
+                    uri: self.file_uri(file_path)?,
                 },
                 position: Position {
                     line,
@@ -269,7 +845,8 @@ impl LspClient {
             partial_result_params: PartialResultParams::default(),
         };
 
-        self.request("textDocument/definition", params).await
+        self.request_with_retry("textDocument/definition", params)
+            .await
     }
 
     pub async fn find_references(
@@ -278,14 +855,14 @@ impl LspClient {
         line: u32,
         column: u32,
         include_declaration: bool,
-    ) -> Result<Option<Vec<Location>>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<Vec<Location>>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = ReferenceParams {
             text_document_position: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: Url::from_file_path(file_path).unwrap(),
+                    uri: self.file_uri(file_path)?,
                 },
                 position: Position {
                     line,
@@ -299,23 +876,26 @@ impl LspClient {
             },
         };
 
-        self.request("textDocument/references", params).await
+        self.request_with_retry("textDocument/references", params)
+            .await
     }
 
     pub async fn format_document(
         &self,
         file_path: &str,
-    ) -> Result<Option<Vec<TextEdit>>, Box<dyn std::error::Error>> {
+        tab_size: u32,
+        insert_spaces: bool,
+    ) -> Result<Option<Vec<TextEdit>>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = DocumentFormattingParams {
             text_document: TextDocumentIdentifier {
-                uri: Url::from_file_path(file_path).unwrap(),
+                uri: self.file_uri(file_path)?,
             },
             options: FormattingOptions {
-                tab_size: 4,
-                insert_spaces: true,
+                tab_size,
+                insert_spaces,
                 ..Default::default()
             },
             work_done_progress_params: WorkDoneProgressParams::default(),
@@ -324,20 +904,46 @@ impl LspClient {
         self.request("textDocument/formatting", params).await
     }
 
+    pub async fn format_range(
+        &self,
+        file_path: &str,
+        range: Range,
+        tab_size: u32,
+        insert_spaces: bool,
+    ) -> Result<Option<Vec<TextEdit>>, LspError> {
+        self.wait_for_ready().await;
+        // Ensure document is open
+        self.open_document(file_path).await?;
+        let params = DocumentRangeFormattingParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.file_uri(file_path)?,
+            },
+            range,
+            options: FormattingOptions {
+                tab_size,
+                insert_spaces,
+                ..Default::default()
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        self.request("textDocument/rangeFormatting", params).await
+    }
+
     pub async fn rename(
         &self,
         file_path: &str,
         line: u32,
         column: u32,
         new_name: &str,
-    ) -> Result<Option<WorkspaceEdit>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<WorkspaceEdit>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = RenameParams {
             text_document_position: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: Url::from_file_path(file_path).unwrap(),
+                    uri: self.file_uri(file_path)?,
                 },
                 position: Position {
                     line,
@@ -351,32 +957,85 @@ impl LspClient {
         self.request("textDocument/rename", params).await
     }
 
+    pub async fn prepare_rename(
+        &self,
+        file_path: &str,
+        line: u32,
+        column: u32,
+    ) -> Result<Option<PrepareRenameResponse>, LspError> {
+        self.wait_for_ready().await;
+        // Ensure document is open
+        self.open_document(file_path).await?;
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.file_uri(file_path)?,
+            },
+            position: Position {
+                line,
+                character: column,
+            },
+        };
+
+        self.request("textDocument/prepareRename", params).await
+    }
+
+    /// Request code actions at a position, limited to the `only` kinds when
+    /// given.
     pub async fn code_actions(
         &self,
         file_path: &str,
         line: u32,
         column: u32,
-    ) -> Result<Option<CodeActionResponse>, Box<dyn std::error::Error>> {
+        only: Option<Vec<CodeActionKind>>,
+    ) -> Result<Option<CodeActionResponse>, LspError> {
+        self.wait_for_ready().await;
+        // Ensure document is open
+        self.open_document(file_path).await?;
+        let uri = self.file_uri(file_path)?;
+        let position = Position {
+            line,
+            character: column,
+        };
+
+        // Quick fixes are only offered for diagnostics passed in the context
+        let cached = self.published_diagnostics.lock().await.get(&uri).cloned();
+        let diagnostics = match cached {
+            Some(diagnostics) => diagnostics,
+            None => self.diagnostics(file_path).await.unwrap_or_default(),
+        };
+        let diagnostics = diagnostics
+            .into_iter()
+            .filter(|diag| diag.range.start <= position && position <= diag.range.end)
+            .collect();
+
+        let range = Range {
+            start: position,
+            end: position,
+        };
+        self.code_actions_in_range(file_path, range, diagnostics, only)
+            .await
+    }
+
+    /// Code actions for `range`, with `diagnostics` as the context the server
+    /// offers quick fixes for.
+    pub async fn code_actions_in_range(
+        &self,
+        file_path: &str,
+        range: Range,
+        diagnostics: Vec<Diagnostic>,
+        only: Option<Vec<CodeActionKind>>,
+    ) -> Result<Option<CodeActionResponse>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = CodeActionParams {
             text_document: TextDocumentIdentifier {
-                uri: Url::from_file_path(file_path).unwrap(),
-            },
-            range: Range {
-                start: Position {
-                    line,
-                    character: column,
-                },
-                end: Position {
-                    line,
-                    character: column,
-                },
+                uri: self.file_uri(file_path)?,
             },
+            range,
             context: CodeActionContext {
-                diagnostics: vec![], // We could pass current diagnostics here
-                only: None,          // Request all types of code actions
+                diagnostics,
+                only,
                 trigger_kind: Some(CodeActionTriggerKind::INVOKED),
                 ..Default::default()
             },
@@ -387,47 +1046,102 @@ impl LspClient {
         self.request("textDocument/codeAction", params).await
     }
 
+    /// Search symbols across the workspace, giving up after `timeout` if one is
+    /// given. Symbols the server streamed before then are still returned, with
+    /// `false` to mark the results as partial.
     pub async fn workspace_symbols(
         &self,
         query: &str,
-    ) -> Result<Option<Vec<SymbolInformation>>, Box<dyn std::error::Error>> {
+        timeout: Option<Duration>,
+    ) -> Result<(Vec<SymbolInformation>, bool), LspError> {
         self.wait_for_ready().await;
+        let token = format!(
+            "workspace-symbols-{}",
+            self.next_partial_token.fetch_add(1, Ordering::Relaxed)
+        );
         let params = WorkspaceSymbolParams {
             query: query.to_string(),
             work_done_progress_params: WorkDoneProgressParams::default(),
-            partial_result_params: PartialResultParams::default(),
+            partial_result_params: PartialResultParams {
+                partial_result_token: Some(NumberOrString::String(token.clone())),
+            },
         };
 
-        self.request("workspace/symbol", params).await
+        self.partial_results
+            .lock()
+            .await
+            .insert(token.clone(), Vec::new());
+        let request = self.request::<_, Option<Vec<SymbolInformation>>>("workspace/symbol", params);
+        // Dropping a timed-out request cancels it on the server
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, request).await.ok(),
+            None => Some(request.await),
+        };
+        let streamed = self
+            .partial_results
+            .lock()
+            .await
+            .remove(&token)
+            .unwrap_or_default();
+
+        let mut symbols: Vec<SymbolInformation> = streamed
+            .into_iter()
+            .filter_map(|item| serde_json::from_value(item).ok())
+            .collect();
+        match result {
+            Some(result) => {
+                symbols.extend(result?.unwrap_or_default());
+                Ok((symbols, true))
+            }
+            None => {
+                debug!(
+                    "workspace/symbol for {:?} timed out with {} partial results",
+                    query,
+                    symbols.len()
+                );
+                Ok((symbols, false))
+            }
+        }
     }
 
+    /// Request inlay hints for lines `start_line..=end_line` (0-indexed),
+    /// defaulting to the whole file.
     pub async fn inlay_hints(
         &self,
         file_path: &str,
-    ) -> Result<Option<Vec<InlayHint>>, Box<dyn std::error::Error>> {
+        start_line: Option<u32>,
+        end_line: Option<u32>,
+    ) -> Result<Option<Vec<InlayHint>>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
 
         // Read the file to get its content and determine the range
-        let content = tokio::fs::read_to_string(file_path).await?;
+        let content = self.read_file(file_path).await?;
         let lines: Vec<&str> = content.lines().collect();
-        let end_line = lines.len().saturating_sub(1) as u32;
-        let end_character = lines.last().map(|line| line.len()).unwrap_or(0) as u32;
+        let last_line = lines.len().saturating_sub(1) as u32;
+        let end = match end_line {
+            // Stop at the start of the following line so the whole end line is covered
+            Some(end_line) if end_line < last_line => Position {
+                line: end_line + 1,
+                character: 0,
+            },
+            _ => Position {
+                line: last_line,
+                character: lines.last().map_or(0, |line| line.encode_utf16().count()) as u32,
+            },
+        };
 
         let params = InlayHintParams {
             text_document: TextDocumentIdentifier {
-                uri: Url::from_file_path(file_path).unwrap(),
+                uri: self.file_uri(file_path)?,
             },
             range: Range {
                 start: Position {
-                    line: 0,
+                    line: start_line.unwrap_or(0).min(last_line),
                     character: 0,
                 },
-                end: Position {
-                    line: end_line,
-                    character: end_character,
-                },
+                end,
             },
             work_done_progress_params: WorkDoneProgressParams::default(),
         };
@@ -435,12 +1149,32 @@ impl LspClient {
         self.request("textDocument/inlayHint", params).await
     }
 
+    /// Fill in the details of a completion item; its `data` is sent back as is.
+    pub async fn resolve_completion_item(
+        &self,
+        item: CompletionItem,
+    ) -> Result<CompletionItem, LspError> {
+        self.wait_for_ready().await;
+        self.request("completionItem/resolve", item).await
+    }
+
+    /// Compute the edit of a code action; its `data` is sent back as is.
+    pub async fn resolve_code_action(&self, action: CodeAction) -> Result<CodeAction, LspError> {
+        self.wait_for_ready().await;
+        self.request("codeAction/resolve", action).await
+    }
+
+    pub async fn resolve_inlay_hint(&self, hint: InlayHint) -> Result<InlayHint, LspError> {
+        self.wait_for_ready().await;
+        self.request("inlayHint/resolve", hint).await
+    }
+
     pub async fn expand_macro(
         &self,
         file_path: &str,
         line: u32,
         column: u32,
-    ) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<Value>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
@@ -448,7 +1182,7 @@ impl LspClient {
         // rust-analyzer uses a custom expandMacro request
         let params = json!({
             "textDocument": {
-                "uri": Url::from_file_path(file_path).unwrap()
+                "uri": self.file_uri(file_path)?
             },
             "position": {
                 "line": line,
@@ -463,13 +1197,13 @@ impl LspClient {
     pub async fn document_symbols(
         &self,
         file_path: &str,
-    ) -> Result<Option<DocumentSymbolResponse>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<DocumentSymbolResponse>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = DocumentSymbolParams {
             text_document: TextDocumentIdentifier {
-                uri: Url::from_file_path(file_path).unwrap(),
+                uri: self.file_uri(file_path)?,
             },
             work_done_progress_params: WorkDoneProgressParams::default(),
             partial_result_params: PartialResultParams::default(),
@@ -483,14 +1217,33 @@ impl LspClient {
         file_path: &str,
         line: u32,
         column: u32,
-    ) -> Result<Option<SignatureHelp>, Box<dyn std::error::Error>> {
+        trigger_character: Option<String>,
+        is_retrigger: Option<bool>,
+    ) -> Result<Option<SignatureHelp>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
+
+        // Without trigger information let the server treat this as a manual invocation
+        let context = if trigger_character.is_none() && is_retrigger.is_none() {
+            None
+        } else {
+            Some(SignatureHelpContext {
+                trigger_kind: if trigger_character.is_some() {
+                    SignatureHelpTriggerKind::TRIGGER_CHARACTER
+                } else {
+                    SignatureHelpTriggerKind::INVOKED
+                },
+                trigger_character,
+                is_retrigger: is_retrigger.unwrap_or(false),
+                active_signature_help: None,
+            })
+        };
+
         let params = SignatureHelpParams {
             text_document_position_params: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: Url::from_file_path(file_path).unwrap(),
+                    uri: self.file_uri(file_path)?,
                 },
                 position: Position {
                     line,
@@ -498,7 +1251,7 @@ impl LspClient {
                 },
             },
             work_done_progress_params: WorkDoneProgressParams::default(),
-            context: None,
+            context,
         };
 
         self.request("textDocument/signatureHelp", params).await
@@ -509,14 +1262,14 @@ impl LspClient {
         file_path: &str,
         line: u32,
         column: u32,
-    ) -> Result<Option<Vec<DocumentHighlight>>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<Vec<DocumentHighlight>>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = DocumentHighlightParams {
             text_document_position_params: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: Url::from_file_path(file_path).unwrap(),
+                    uri: self.file_uri(file_path)?,
                 },
                 position: Position {
                     line,
@@ -527,20 +1280,21 @@ impl LspClient {
             partial_result_params: PartialResultParams::default(),
         };
 
-        self.request("textDocument/documentHighlight", params).await
+        self.request_with_retry("textDocument/documentHighlight", params)
+            .await
     }
 
     pub async fn selection_range(
         &self,
         file_path: &str,
         positions: Vec<Position>,
-    ) -> Result<Option<Vec<SelectionRange>>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<Vec<SelectionRange>>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = SelectionRangeParams {
             text_document: TextDocumentIdentifier {
-                uri: Url::from_file_path(file_path).unwrap(),
+                uri: self.file_uri(file_path)?,
             },
             positions,
             work_done_progress_params: WorkDoneProgressParams::default(),
@@ -550,10 +1304,7 @@ impl LspClient {
         self.request("textDocument/selectionRange", params).await
     }
 
-    pub async fn runnables(
-        &self,
-        file_path: &str,
-    ) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+    pub async fn runnables(&self, file_path: &str) -> Result<Option<Value>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
@@ -561,7 +1312,7 @@ impl LspClient {
         // rust-analyzer uses a custom runnables request
         let params = json!({
             "textDocument": {
-                "uri": Url::from_file_path(file_path).unwrap()
+                "uri": self.file_uri(file_path)?
             }
         });
 
@@ -574,14 +1325,135 @@ impl LspClient {
         file_path: &str,
         line: u32,
         column: u32,
-    ) -> Result<Option<Vec<Location>>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<Vec<Location>>, LspError> {
         self.wait_for_ready().await;
         // Ensure document is open
         self.open_document(file_path).await?;
         let params = GotoImplementationParams {
             text_document_position_params: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: Url::from_file_path(file_path).unwrap(),
+                    uri: self.file_uri(file_path)?,
+                },
+                position: Position {
+                    line,
+                    character: column,
+                },
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        self.request_with_retry("textDocument/implementation", params)
+            .await
+    }
+
+    pub async fn document_links(
+        &self,
+        file_path: &str,
+    ) -> Result<Option<Vec<DocumentLink>>, LspError> {
+        self.wait_for_ready().await;
+        // Ensure document is open
+        self.open_document(file_path).await?;
+        let params = DocumentLinkParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.file_uri(file_path)?,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        self.request("textDocument/documentLink", params).await
+    }
+
+    pub async fn resolve_document_link(
+        &self,
+        link: DocumentLink,
+    ) -> Result<DocumentLink, LspError> {
+        self.wait_for_ready().await;
+        self.request("documentLink/resolve", link).await
+    }
+
+    pub async fn code_lenses(&self, file_path: &str) -> Result<Option<Vec<CodeLens>>, LspError> {
+        self.wait_for_ready().await;
+        // Ensure document is open
+        self.open_document(file_path).await?;
+        let params = CodeLensParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.file_uri(file_path)?,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        self.request("textDocument/codeLens", params).await
+    }
+
+    pub async fn resolve_code_lens(&self, lens: CodeLens) -> Result<CodeLens, LspError> {
+        self.wait_for_ready().await;
+        self.request("codeLens/resolve", lens).await
+    }
+
+    pub async fn interpret_function(
+        &self,
+        file_path: &str,
+        line: u32,
+        column: u32,
+    ) -> Result<Option<String>, LspError> {
+        self.wait_for_ready().await;
+        // Ensure document is open
+        self.open_document(file_path).await?;
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: self.file_uri(file_path)?,
+            },
+            position: Position {
+                line,
+                character: column,
+            },
+        };
+
+        // This is a rust-analyzer specific extension, not standard LSP
+        self.request("rust-analyzer/interpretFunction", params)
+            .await
+    }
+
+    pub async fn view_item_tree(&self, file_path: &str) -> Result<Option<String>, LspError> {
+        self.wait_for_ready().await;
+        // Ensure document is open
+        self.open_document(file_path).await?;
+        let params = json!({
+            "textDocument": { "uri": self.file_uri(file_path)? }
+        });
+
+        // This is a rust-analyzer specific extension, not standard LSP
+        self.request("rust-analyzer/viewItemTree", params).await
+    }
+
+    pub async fn view_file_text(&self, file_path: &str) -> Result<Option<String>, LspError> {
+        self.wait_for_ready().await;
+        // Ensure document is open
+        self.open_document(file_path).await?;
+        let params = TextDocumentIdentifier {
+            uri: self.file_uri(file_path)?,
+        };
+
+        // This is a rust-analyzer specific extension, not standard LSP
+        self.request("rust-analyzer/viewFileText", params).await
+    }
+
+    pub async fn moniker(
+        &self,
+        file_path: &str,
+        line: u32,
+        column: u32,
+    ) -> Result<Option<Vec<Moniker>>, LspError> {
+        self.wait_for_ready().await;
+        // Ensure document is open
+        self.open_document(file_path).await?;
+        let params = MonikerParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: self.file_uri(file_path)?,
                 },
                 position: Position {
                     line,
@@ -592,18 +1464,108 @@ impl LspClient {
             partial_result_params: PartialResultParams::default(),
         };
 
-        self.request("textDocument/implementation", params).await
+        self.request("textDocument/moniker", params).await
+    }
+
+    /// The server settings in effect, including any changed with `configure`.
+    pub async fn settings(&self) -> Value {
+        self.settings.lock().await.clone()
+    }
+
+    /// Merge `overrides` into the server settings and notify the server,
+    /// which then pulls them with `workspace/configuration`. Returns the
+    /// effective settings.
+    pub async fn configure(&self, overrides: Value) -> Result<Value, LspError> {
+        self.wait_for_ready().await;
+        let settings = {
+            let mut settings = self.settings.lock().await;
+            merge_settings(&mut settings, overrides);
+            settings.clone()
+        };
+
+        let params = DidChangeConfigurationParams {
+            settings: settings.clone(),
+        };
+        self.notify("workspace/didChangeConfiguration", params)
+            .await?;
+
+        Ok(settings)
+    }
+
+    pub async fn execute_command(
+        &self,
+        command: &str,
+        arguments: Vec<Value>,
+    ) -> Result<Option<Value>, LspError> {
+        self.wait_for_ready().await;
+        let params = ExecuteCommandParams {
+            command: command.to_string(),
+            arguments,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        self.request("workspace/executeCommand", params).await
+    }
+
+    /// Check that the server answers requests. `$/` requests it doesn't know
+    /// must be answered with an error, so any response proves it is alive.
+    pub async fn ping(&self) -> Result<(), LspError> {
+        match self.request::<_, Value>("$/ping", Value::Null).await {
+            Ok(_) | Err(LspError::Protocol { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stop the server with `shutdown` and `exit`, killing it if it doesn't
+    /// exit in time.
+    pub async fn shutdown(mut self) -> Result<(), LspError> {
+        let requested = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+            self.request::<_, Value>("shutdown", Value::Null).await?;
+            self.notify("exit", Value::Null).await
+        })
+        .await;
+        if let Ok(Err(e)) = &requested {
+            warn!("Language server did not shut down cleanly: {}", e);
+        }
+
+        let Some(mut process) = self.process.take() else {
+            return Ok(());
+        };
+        match tokio::time::timeout(SHUTDOWN_TIMEOUT, process.wait()).await {
+            Ok(status) => {
+                info!("Language server exited with {}", status?);
+                Ok(())
+            }
+            Err(_) => {
+                warn!("Language server did not exit in time, killing it");
+                Ok(process.kill().await?)
+            }
+        }
+    }
+
+    /// Send an arbitrary request and return its raw result, for debugging and
+    /// for extensions that have no dedicated method yet.
+    pub async fn raw_request(&self, method: &str, params: Value) -> Result<Value, LspError> {
+        self.wait_for_ready().await;
+        debug!("Raw LSP request {}: {}", method, params);
+        let result = self.request(method, params).await;
+        debug!("Raw LSP response for {}: {:?}", method, result);
+        result
     }
 
     async fn request<P: serde::Serialize, R: serde::de::DeserializeOwned>(
         &self,
         method: &str,
         params: P,
-    ) -> Result<R, Box<dyn std::error::Error>> {
-        let mut id = self.request_id.lock().await;
-        *id += 1;
-        let request_id = *id;
-
+    ) -> Result<R, LspError> {
+        // Held until the response is read; the semaphore is never closed
+        let _permit = self
+            .request_permits
+            .acquire()
+            .await
+            .expect("request semaphore closed");
+
+        let request_id = self.request_id.fetch_add(1, Ordering::Relaxed) + 1;
         let request = json!({
             "jsonrpc": "2.0",
             "id": request_id,
@@ -611,24 +1573,106 @@ impl LspClient {
             "params": params
         });
 
-        self.send_message(&request).await?;
+        // Registered before sending so the reader can't see the response first
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().await.insert(request_id, sender);
+        if self.server_exited.load(Ordering::Acquire) {
+            self.pending.lock().await.remove(&request_id);
+            return Err(LspError::ServerExited);
+        }
+
+        let mut cancel = CancelOnDrop {
+            stdin: self.stdin.clone(),
+            pending: self.pending.clone(),
+            id: request_id,
+            armed: true,
+        };
+        if let Err(e) = self.send_message(&request).await {
+            cancel.armed = false;
+            self.pending.lock().await.remove(&request_id);
+            return Err(e);
+        }
 
-        let response = self.read_response(request_id).await?;
+        // The reader drops the sender if the server exits before answering, and
+        // there is then no one to cancel the request with
+        let response = receiver.await;
+        cancel.armed = false;
+        let response = response.map_err(|_| LspError::ServerExited)?;
 
         if let Some(error) = response.get("error") {
-            return Err(format!("LSP error: {:?}", error).into());
+            return Err(LspError::Protocol {
+                method: method.to_string(),
+                error: ResponseError::from_value(error),
+            });
         }
 
-        let result = response.get("result").ok_or("Missing result in response")?;
+        // A missing result is read as null, which every optional result accepts
+        let result = match response.get("result") {
+            Some(result) => result.clone(),
+            None => {
+                debug!("{} response has no result, treating it as null", method);
+                Value::Null
+            }
+        };
 
-        Ok(serde_json::from_value(result.clone())?)
+        serde_json::from_value(result).map_err(|error| LspError::UnexpectedResult {
+            method: method.to_string(),
+            error,
+        })
     }
 
-    async fn notify<P: serde::Serialize>(
+    /// Wait up to `timeout` for the server to publish diagnostics for `uri`.
+    /// Returns `None` if nothing was published in time.
+    async fn wait_for_published_diagnostics(
         &self,
-        method: &str,
-        params: P,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        uri: &Url,
+        timeout: Duration,
+    ) -> Result<Option<Vec<Diagnostic>>, LspError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Created before checking so a publish in between still wakes it
+            let published = self.diagnostics_published.notified();
+            if let Some(diagnostics) = self.published_diagnostics.lock().await.get(uri) {
+                return Ok(Some(diagnostics.clone()));
+            }
+            if self.server_exited.load(Ordering::Acquire) {
+                return Err(LspError::ServerExited);
+            }
+
+            if tokio::time::timeout_at(deadline, published).await.is_err() {
+                debug!("No diagnostics published for {} within {:?}", uri, timeout);
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Like `request`, but retries with a growing delay while the server answers
+    /// "content modified". Used for navigation requests, which rust-analyzer
+    /// cancels this way while it is still indexing.
+    async fn request_with_retry<P, R>(&self, method: &str, params: P) -> Result<R, LspError>
+    where
+        P: serde::Serialize + Clone,
+        R: serde::de::DeserializeOwned,
+    {
+        let mut attempt = 0;
+        loop {
+            match self.request(method, params.clone()).await {
+                Err(LspError::Protocol { ref error, .. })
+                    if error.is_content_modified() && attempt < self.retry.retries =>
+                {
+                    attempt += 1;
+                    debug!(
+                        "{} returned content modified, retrying ({}/{})",
+                        method, attempt, self.retry.retries
+                    );
+                    tokio::time::sleep(self.retry.delay * attempt).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn notify<P: serde::Serialize>(&self, method: &str, params: P) -> Result<(), LspError> {
         let notification = json!({
             "jsonrpc": "2.0",
             "method": method,
@@ -638,11 +1682,17 @@ impl LspClient {
         self.send_message(&notification).await
     }
 
-    async fn send_message(&self, message: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    async fn send_message(&self, message: &Value) -> Result<(), LspError> {
         let content = serde_json::to_string(message)?;
-        let header = format!("Content-Length: {}\r\n\r\n", content.len());
-
         let mut stdin = self.stdin.lock().await;
+        Self::write_frame(&mut *stdin, &content).await
+    }
+
+    async fn write_frame(
+        stdin: &mut (impl AsyncWrite + Unpin),
+        content: &str,
+    ) -> Result<(), LspError> {
+        let header = format!("Content-Length: {}\r\n\r\n", content.len());
         stdin.write_all(header.as_bytes()).await?;
         stdin.write_all(content.as_bytes()).await?;
         stdin.flush().await?;
@@ -652,39 +1702,600 @@ impl LspClient {
         Ok(())
     }
 
-    async fn read_response(&self, expected_id: i64) -> Result<Value, Box<dyn std::error::Error>> {
-        let mut stdout = self.stdout.lock().await;
+    /// Read messages until the server's output closes, handing each response
+    /// to the request waiting for it and anything else, such as server requests
+    /// and notifications, to `responder`.
+    async fn read_loop(
+        mut stdout: BufReader<ServerOutput>,
+        pending: PendingRequests,
+        responder: Responder,
+        server_exited: Arc<AtomicBool>,
+    ) {
+        loop {
+            let message = match Self::read_message(&mut stdout).await {
+                Ok(message) => message,
+                Err(LspError::ServerExited) => {
+                    info!("Language server closed its output");
+                    break;
+                }
+                Err(e) => {
+                    warn!("Stopped reading from the language server: {}", e);
+                    break;
+                }
+            };
+            debug!("Received LSP message: {}", message);
+
+            let method = message.get("method").and_then(Value::as_str);
+            match (message.get("id").and_then(Value::as_i64), method) {
+                (Some(id), None) => {
+                    // Nobody waits for the answer to a cancelled request
+                    if let Some(sender) = pending.lock().await.remove(&id) {
+                        let _ = sender.send(message);
+                    }
+                }
+                _ => {
+                    if let Err(e) = responder.handle(&message).await {
+                        warn!("Failed to handle a message from the language server: {}", e);
+                    }
+                }
+            }
+        }
+
+        // Dropping the senders fails every request still waiting
+        server_exited.store(true, Ordering::Release);
+        pending.lock().await.clear();
+        responder.diagnostics_published.notify_waiters();
+    }
 
+    /// Read one base-protocol frame: `Key: Value` headers in any order, a blank
+    /// line, then a body of `Content-Length` bytes.
+    async fn read_message(stdout: &mut (impl AsyncBufRead + Unpin)) -> Result<Value, LspError> {
         loop {
-            let mut header = String::new();
-            stdout.read_line(&mut header).await?;
+            let mut headers = HashMap::new();
+            loop {
+                let mut line = String::new();
+                if stdout.read_line(&mut line).await? == 0 {
+                    return Err(LspError::ServerExited);
+                }
+
+                let line = line.trim_end_matches(['\r', '\n']);
+                if line.is_empty() {
+                    break;
+                }
+
+                match line.split_once(':') {
+                    Some((key, value)) => {
+                        headers.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+                    }
+                    None => {
+                        return Err(LspError::InvalidFrame(format!(
+                            "malformed header line: {:?}",
+                            line
+                        )))
+                    }
+                }
+            }
+
+            // Tolerate stray blank lines between frames
+            if headers.is_empty() {
+                continue;
+            }
+
+            let length: usize = headers
+                .get("content-length")
+                .ok_or_else(|| LspError::InvalidFrame("missing Content-Length header".to_string()))?
+                .parse()
+                .map_err(|e| {
+                    LspError::InvalidFrame(format!("invalid Content-Length header: {}", e))
+                })?;
 
-            if header.starts_with("Content-Length:") {
-                let length: usize = header
-                    .trim_start_matches("Content-Length:")
-                    .trim()
-                    .parse()?;
+            let mut content = vec![0; length];
+            stdout.read_exact(&mut content).await?;
+
+            return Ok(serde_json::from_slice(&content)?);
+        }
+    }
+}
 
-                stdout.read_line(&mut header).await?;
+/// A ranged change turning `old` into `new`, replacing what lies between their
+/// common prefix and suffix. Returns `None` when most of the document changed,
+/// where sending the full text is just as cheap.
+fn incremental_change(old: &str, new: &str) -> Option<TextDocumentContentChangeEvent> {
+    let mut prefix: usize = old
+        .chars()
+        .zip(new.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+    // Never split a \r\n line break, which positions can't point into
+    while prefix > 0 && old.as_bytes()[prefix - 1] == b'\r' {
+        prefix -= 1;
+    }
+    let mut suffix: usize = old[prefix..]
+        .chars()
+        .rev()
+        .zip(new[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+    if suffix > 0
+        && old[..old.len() - suffix].ends_with('\r')
+        && old[old.len() - suffix..].starts_with('\n')
+    {
+        suffix -= 1;
+    }
 
-                let mut content = vec![0; length];
-                stdout.read_exact(&mut content).await?;
+    let old_end = old.len() - suffix;
+    let new_end = new.len() - suffix;
+    if new_end - prefix > new.len() / 2 {
+        return None;
+    }
+
+    Some(TextDocumentContentChangeEvent {
+        range: Some(Range {
+            start: position_at(old, prefix),
+            end: position_at(old, old_end),
+        }),
+        range_length: None,
+        text: new[prefix..new_end].to_string(),
+    })
+}
+
+/// Deep-merge `overrides` into `settings`: objects are merged key by key and
+/// any other value replaces the current one.
+fn merge_settings(settings: &mut Value, overrides: Value) {
+    match (settings, overrides) {
+        (Value::Object(settings), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge_settings(settings.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (settings, overrides) => *settings = overrides,
+    }
+}
 
-                let response: Value = serde_json::from_slice(&content)?;
-                debug!("Received LSP response: {}", response);
+/// Handles what the server sends besides responses: requests to the client,
+/// diagnostics and progress.
+struct Responder {
+    stdin: Arc<Mutex<ServerInput>>,
+    settings: Arc<Mutex<Value>>,
+    /// The `workspace/configuration` section the settings as a whole belong
+    /// to, named after the server's command, e.g. "rust-analyzer".
+    own_section: String,
+    published_diagnostics: Arc<Mutex<HashMap<Url, Vec<Diagnostic>>>>,
+    diagnostics_published: Arc<Notify>,
+    partial_results: Arc<Mutex<HashMap<String, Vec<Value>>>>,
+    work_progress: Arc<Mutex<HashMap<String, WorkProgress>>>,
+}
 
-                if let Some(id) = response.get("id") {
-                    if id.as_i64() == Some(expected_id) {
-                        return Ok(response);
+impl Responder {
+    /// Handle a message that isn't the response being waited for.
+    async fn handle(&self, message: &Value) -> Result<(), LspError> {
+        let method = message.get("method").and_then(Value::as_str);
+        match (message.get("id"), method) {
+            // A request from the server, answer it so it isn't left waiting
+            (Some(id), Some(method)) => self.answer(id, method, message.get("params")).await,
+            (None, Some("textDocument/publishDiagnostics")) => {
+                let params = message.get("params").cloned().unwrap_or_default();
+                if let Ok(params) = serde_json::from_value::<PublishDiagnosticsParams>(params) {
+                    self.published_diagnostics
+                        .lock()
+                        .await
+                        .insert(params.uri, params.diagnostics);
+                    self.diagnostics_published.notify_waiters();
+                }
+                Ok(())
+            }
+            // Partial results come as arrays, work done progress as objects
+            (None, Some("$/progress")) => {
+                let params = message.get("params");
+                let token =
+                    params
+                        .and_then(|params| params.get("token"))
+                        .map(|token| match token {
+                            Value::String(token) => token.clone(),
+                            other => other.to_string(),
+                        });
+                let value = params.and_then(|params| params.get("value"));
+                match (token, value) {
+                    (Some(token), Some(Value::Array(items))) => {
+                        if let Some(results) = self.partial_results.lock().await.get_mut(&token) {
+                            results.extend(items.iter().cloned());
+                        }
                     }
+                    (Some(token), Some(value)) => self.record_work_progress(token, value).await,
+                    _ => {}
                 }
+                Ok(())
+            }
+            // Other notifications and answers to cancelled requests
+            _ => Ok(()),
+        }
+    }
+
+    async fn record_work_progress(&self, token: String, value: &Value) {
+        let message = value
+            .get("message")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let percentage = value
+            .get("percentage")
+            .and_then(Value::as_u64)
+            .map(|p| p as u32);
+
+        let mut progress = self.work_progress.lock().await;
+        match value.get("kind").and_then(Value::as_str) {
+            Some("begin") => {
+                let title = value
+                    .get("title")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                debug!("Server work started: {}", title);
+                progress.insert(
+                    token,
+                    WorkProgress {
+                        title,
+                        message,
+                        percentage,
+                    },
+                );
+            }
+            Some("report") => {
+                if let Some(work) = progress.get_mut(&token) {
+                    work.message = message.or(work.message.take());
+                    work.percentage = percentage.or(work.percentage);
+                }
+            }
+            Some("end") => {
+                if let Some(work) = progress.remove(&token) {
+                    debug!("Server work finished: {}", work.title);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The settings for a `workspace/configuration` section: all of them for
+    /// no section or the server's own, otherwise the value at the section's
+    /// dotted path, e.g. "python.analysis".
+    fn settings_section(&self, settings: &Value, section: Option<&str>) -> Value {
+        match section {
+            None => settings.clone(),
+            Some(section) if section == self.own_section => settings.clone(),
+            Some(section) => section
+                .split('.')
+                .try_fold(settings, |value, key| value.get(key))
+                .cloned()
+                .unwrap_or(Value::Null),
+        }
+    }
+
+    async fn answer(
+        &self,
+        id: &Value,
+        method: &str,
+        params: Option<&Value>,
+    ) -> Result<(), LspError> {
+        let result = match method {
+            "workspace/configuration" => {
+                let settings = self.settings.lock().await;
+                let items = params
+                    .and_then(|params| params.get("items"))
+                    .and_then(Value::as_array)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                items
+                    .iter()
+                    .map(|item| {
+                        let section = item.get("section").and_then(Value::as_str);
+                        self.settings_section(&settings, section)
+                    })
+                    .collect()
             }
+            // Progress token creation, capability registration and the like
+            // only need an acknowledgement
+            _ => Value::Null,
+        };
+
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": result
+        });
+        let mut stdin = self.stdin.lock().await;
+        LspClient::write_frame(&mut *stdin, &response.to_string()).await
+    }
+}
+
+/// Sends `$/cancelRequest` if a request future is dropped before its response
+/// arrives, e.g. because the MCP client cancelled the tool call.
+struct CancelOnDrop {
+    stdin: Arc<Mutex<ServerInput>>,
+    pending: PendingRequests,
+    id: i64,
+    armed: bool,
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
         }
+
+        debug!("Cancelling LSP request {}", self.id);
+        let stdin = self.stdin.clone();
+        let pending = self.pending.clone();
+        let id = self.id;
+        let cancel = json!({
+            "jsonrpc": "2.0",
+            "method": "$/cancelRequest",
+            "params": { "id": self.id }
+        })
+        .to_string();
+        tokio::spawn(async move {
+            pending.lock().await.remove(&id);
+            let mut stdin = stdin.lock().await;
+            let _ = LspClient::write_frame(&mut *stdin, &cancel).await;
+        });
     }
 }
 
 impl Drop for LspClient {
     fn drop(&mut self) {
-        let _ = self.process.kill();
+        self.reader.abort();
+        if let Some(process) = &mut self.process {
+            let _ = process.start_kill();
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_in_flight_are_capped() {
+        let root = mock::workspace(&[]);
+        let (mut client, mut server) = mock::connect(&root, json!({})).await;
+        client.request_permits = Semaphore::new(2);
+
+        let slow = || client.request::<_, Value>("test/slow", Value::Null);
+        let requests = async { tokio::join!(slow(), slow(), slow(), slow(), slow()) };
+        let answer = async {
+            let mut waiting = Vec::new();
+            let mut most_in_flight = 0;
+            for _ in 0..5 {
+                // Take everything the client sends before it has to wait
+                while let Ok(request) =
+                    tokio::time::timeout(Duration::from_millis(100), server.receive()).await
+                {
+                    waiting.push(request);
+                }
+                most_in_flight = most_in_flight.max(waiting.len());
+                let request = waiting.remove(0);
+                server.respond(&request, Value::Null).await;
+            }
+            most_in_flight
+        };
+
+        let ((a, b, c, d, e), most_in_flight) = tokio::join!(requests, answer);
+        for result in [a, b, c, d, e] {
+            assert_eq!(result.unwrap(), Value::Null);
+        }
+        assert_eq!(most_in_flight, 2);
+    }
+
+    #[tokio::test]
+    async fn dropped_request_is_cancelled() {
+        let root = mock::workspace(&[]);
+        let (client, mut server) = mock::connect(&root, json!({})).await;
+
+        // The request is dropped once the timeout elapses, unanswered
+        let slow = tokio::time::timeout(
+            Duration::from_millis(100),
+            client.request::<_, Value>("test/slow", Value::Null),
+        );
+        let (result, request) = tokio::join!(slow, server.expect("test/slow"));
+        assert!(result.is_err());
+
+        let cancel = server.expect("$/cancelRequest").await;
+        assert_eq!(cancel["params"]["id"], request["id"]);
+        assert!(client.pending.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn requests_are_not_cancelled_once_the_server_exits() {
+        let root = mock::workspace(&[]);
+        let (client, mut server) = mock::connect(&root, json!({})).await;
+
+        let exit = async {
+            server.expect("test/slow").await;
+            server.exit().await;
+        };
+        let (result, ()) = tokio::join!(client.request::<_, Value>("test/slow", Value::Null), exit);
+        assert!(matches!(result, Err(LspError::ServerExited)));
+
+        let cancel =
+            tokio::time::timeout(Duration::from_millis(100), server.expect("$/cancelRequest"));
+        assert!(cancel.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn null_and_missing_results_are_none() {
+        let root = mock::workspace(&[("src/lib.rs", "fn main() {}\n")]);
+        let (client, mut server) = mock::connect(&root, json!({})).await;
+
+        for response in [json!({ "result": null }), json!({})] {
+            let answer = async {
+                let request = server.expect("textDocument/hover").await;
+                server.reply(&request, response.clone()).await;
+            };
+            let (hover, ()) = tokio::join!(client.hover("src/lib.rs", 0, 3), answer);
+            assert!(hover.unwrap().is_none(), "{}", response);
+
+            let answer = async {
+                let request = server.expect("textDocument/definition").await;
+                server.reply(&request, response.clone()).await;
+            };
+            let (definition, ()) = tokio::join!(client.goto_definition("src/lib.rs", 0, 3), answer);
+            assert!(definition.unwrap().is_none(), "{}", response);
+        }
+    }
+
+    #[tokio::test]
+    async fn unexpected_result_names_the_method() {
+        let root = mock::workspace(&[("src/lib.rs", "fn main() {}\n")]);
+        let (client, mut server) = mock::connect(&root, json!({})).await;
+
+        let answer = async {
+            let request = server.expect("textDocument/hover").await;
+            server.respond(&request, json!(42)).await;
+        };
+        let (hover, ()) = tokio::join!(client.hover("src/lib.rs", 0, 3), answer);
+
+        let error = hover.unwrap_err();
+        assert!(
+            matches!(&error, LspError::UnexpectedResult { method, .. } if method == "textDocument/hover"),
+            "{:?}",
+            error
+        );
+        assert!(error
+            .to_string()
+            .starts_with("textDocument/hover returned an unexpected result"));
+    }
+
+    /// The range and text of the change from `old` to `new`.
+    fn change(old: &str, new: &str) -> ((u32, u32), (u32, u32), String) {
+        let change = incremental_change(old, new).expect("expected a ranged change");
+        let range = change.range.unwrap();
+        (
+            (range.start.line, range.start.character),
+            (range.end.line, range.end.character),
+            change.text,
+        )
+    }
+
+    #[test]
+    fn incremental_change_with_lf_line_breaks() {
+        assert_eq!(
+            change("fn a() {}\nfn b() {}\n", "fn a() {}\nfn c() {}\n"),
+            ((1, 3), (1, 4), "c".to_string())
+        );
+        assert_eq!(
+            change("fn a() {}\nfn b() {}\n", "fn a() {}\n\nfn b() {}\n"),
+            ((1, 0), (1, 0), "\n".to_string())
+        );
+    }
+
+    #[test]
+    fn incremental_change_never_splits_crlf() {
+        assert_eq!(
+            change("fn a() {}\r\nfn b() {}\r\n", "fn a() {}\r\nfn c() {}\r\n"),
+            ((1, 3), (1, 4), "c".to_string())
+        );
+        // The common suffix starts at the \n of the first line break
+        assert_eq!(
+            change("one\r\ntwo\r\nthree\r\n", "one\ntwo\r\nthree\r\n"),
+            ((0, 3), (1, 0), "\n".to_string())
+        );
+        // The common prefix ends at the \r of the first line break
+        assert_eq!(
+            change("ab\r\ncd\r\nef\r\n", "ab\rX\ncd\r\nef\r\n"),
+            ((0, 2), (1, 0), "\rX\n".to_string())
+        );
+    }
+
+    #[test]
+    fn incremental_change_counts_utf16_columns() {
+        // é is one UTF-16 unit and the emoji two, so `a` is at column 18
+        assert_eq!(
+            change("let s = \"héllo 😀 a\";\n", "let s = \"héllo 😀 b\";\n"),
+            ((0, 18), (0, 19), "b".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn changes_are_sent_as_ranges() {
+        let root = mock::workspace(&[("src/lib.rs", "fn a() {}\nfn b() {}\n")]);
+        let (client, mut server) = mock::connect(&root, json!({ "textDocumentSync": 2 })).await;
+
+        client.open_document("src/lib.rs").await.unwrap();
+        client
+            .write_file("src/lib.rs", "fn a() {}\nfn c() {}\n")
+            .await
+            .unwrap();
+
+        let change = server.expect("textDocument/didChange").await;
+        assert_eq!(
+            change["params"]["contentChanges"],
+            json!([{
+                "range": {
+                    "start": { "line": 1, "character": 3 },
+                    "end": { "line": 1, "character": 4 }
+                },
+                "text": "c"
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn parent_dir_escapes_are_outside_the_workspace() {
+        let root = mock::workspace(&[("src/lib.rs", "fn main() {}\n")]);
+        let outside = mock::workspace(&[("secret.rs", "fn secret() {}\n")]);
+        let (client, _server) = mock::connect(&root, json!({})).await;
+
+        let escape = format!(
+            "src/../../{}/secret.rs",
+            outside.file_name().unwrap().to_string_lossy()
+        );
+        assert!(matches!(
+            client.open_document(&escape).await,
+            Err(LspError::OutsideWorkspace(_))
+        ));
+        assert!(matches!(
+            client
+                .open_document(&outside.join("secret.rs").to_string_lossy())
+                .await,
+            Err(LspError::OutsideWorkspace(_))
+        ));
+        assert!(client.is_in_workspace(&root.join("src/../src/lib.rs")));
+    }
+
+    #[tokio::test]
+    async fn configuration_is_served_by_section() {
+        let root = mock::workspace(&[]);
+        let (client, mut server) = mock::connect(&root, json!({})).await;
+        let settings = client
+            .configure(json!({ "python": { "analysis": { "strict": true } } }))
+            .await
+            .unwrap();
+
+        server
+            .send(json!({
+                "jsonrpc": "2.0",
+                "id": "configuration",
+                "method": "workspace/configuration",
+                "params": { "items": [
+                    { "section": "rust-analyzer" },
+                    {},
+                    { "section": "python.analysis" },
+                    { "section": "gopls" }
+                ] }
+            }))
+            .await;
+        let response = loop {
+            let message = server.receive().await;
+            if message["id"] == "configuration" {
+                break message;
+            }
+        };
+        assert_eq!(
+            response["result"],
+            json!([settings, settings, { "strict": true }, null])
+        );
     }
 }
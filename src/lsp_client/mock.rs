@@ -0,0 +1,124 @@
+//! A scripted language server for tests, talking to an `LspClient` over an
+//! in-memory pipe.
+
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::{AsyncWriteExt, BufReader, DuplexStream, WriteHalf};
+use tokio::sync::mpsc;
+
+use super::LspClient;
+use crate::config::LanguageServerConfig;
+
+/// The server end of the pipe. Messages from the client are read on a task of
+/// their own, so waiting for one can be timed out without losing any.
+pub struct MockServer {
+    messages: mpsc::UnboundedReceiver<Value>,
+    output: WriteHalf<DuplexStream>,
+}
+
+impl MockServer {
+    /// The next message from the client.
+    pub async fn receive(&mut self) -> Value {
+        self.messages
+            .recv()
+            .await
+            .expect("client closed the connection")
+    }
+
+    /// Skip messages until the client sends `method`, and return that one.
+    pub async fn expect(&mut self, method: &str) -> Value {
+        loop {
+            let message = self.receive().await;
+            if message["method"] == method {
+                return message;
+            }
+        }
+    }
+
+    pub async fn send(&mut self, message: Value) {
+        LspClient::write_frame(&mut self.output, &message.to_string())
+            .await
+            .expect("client closed the connection");
+    }
+
+    /// Close the server's output, as if it had exited.
+    pub async fn exit(&mut self) {
+        self.output.shutdown().await.unwrap();
+    }
+
+    /// Answer `request` with `result`.
+    pub async fn respond(&mut self, request: &Value, result: Value) {
+        self.reply(request, json!({ "result": result })).await;
+    }
+
+    /// Answer `request` with the fields of `response`, such as `result` or
+    /// `error`, adding the request's id.
+    pub async fn reply(&mut self, request: &Value, mut response: Value) {
+        response["jsonrpc"] = json!("2.0");
+        response["id"] = request["id"].clone();
+        self.send(response).await;
+    }
+}
+
+/// A client initialized against a mock server that advertises `capabilities`,
+/// with `root` as its only workspace folder.
+pub async fn connect(root: &Path, capabilities: Value) -> (LspClient, MockServer) {
+    let (client_end, server_end) = tokio::io::duplex(1 << 16);
+    let (client_output, client_input) = tokio::io::split(client_end);
+    let (server_input, server_output) = tokio::io::split(server_end);
+
+    let (sender, messages) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut input = BufReader::new(server_input);
+        while let Ok(message) = LspClient::read_message(&mut input).await {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    let mut server = MockServer {
+        messages,
+        output: server_output,
+    };
+
+    let mut client = LspClient::connect(
+        Box::new(client_input),
+        Box::new(client_output),
+        None,
+        &[root.to_path_buf()],
+        &LanguageServerConfig::default(),
+    );
+    let initialize = async {
+        let request = server.expect("initialize").await;
+        let result = json!({
+            "capabilities": capabilities,
+            "serverInfo": { "name": "mock" }
+        });
+        server.respond(&request, result).await;
+        server.expect("initialized").await;
+    };
+    let (handshake, ()) = tokio::join!(client.handshake(), initialize);
+    handshake.expect("handshake with the mock server failed");
+
+    (client, server)
+}
+
+/// A new directory holding `files`, given as relative path and text, for use
+/// as a workspace root.
+pub fn workspace(files: &[(&str, &str)]) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let root = std::env::temp_dir().join(format!(
+        "language-server-mcp-test-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&root).unwrap();
+    for (path, text) in files {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+    // Canonical, like the roots `Config` hands the client
+    root.canonicalize().unwrap()
+}
@@ -3,25 +3,120 @@ use rmcp::{
     model::*,
     schemars,
     service::RequestContext,
-    tool, tool_handler, tool_router,
-    transport::stdio,
+    tool, tool_router,
+    transport::{sse_server::SseServer, stdio},
     ErrorData as McpError, RoleServer, ServerHandler, ServiceExt,
 };
+use std::collections::BTreeMap;
 use std::future::Future;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use tokio::sync::Mutex;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::{self, EnvFilter};
 
+mod config;
+mod diff;
+mod error;
 mod lsp_client;
+mod markdown;
+mod position;
+mod prompts;
+mod resources;
+use config::{Config, Transport};
+use error::LspError;
 use lsp_client::LspClient;
 
+impl From<LspError> for McpError {
+    fn from(e: LspError) -> Self {
+        match e {
+            LspError::BadPath(_) | LspError::OutsideWorkspace(_) => {
+                McpError::invalid_params(e.to_string(), None)
+            }
+            // Keep the server's code and data so clients can tell retryable errors
+            // (e.g. content modified) apart from real failures
+            LspError::Protocol { method, error } => McpError::new(
+                ErrorCode(error.code as i32),
+                format!("LSP error: {} failed: {}", method, error.message),
+                error.data,
+            ),
+            LspError::Io(ref io) if io.kind() == std::io::ErrorKind::NotFound => {
+                McpError::invalid_params(format!("File not found: {}", io), None)
+            }
+            _ => McpError::internal_error(format!("LSP error: {}", e), None),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DocFormat {
+    #[default]
+    Markdown,
+    Plaintext,
+}
+
+impl DocFormat {
+    fn render(self, markdown: String) -> String {
+        match self {
+            DocFormat::Markdown => markdown,
+            DocFormat::Plaintext => markdown::to_plaintext(&markdown),
+        }
+    }
+
+    fn render_markup(self, markup: lsp_types::MarkupContent) -> String {
+        match markup.kind {
+            lsp_types::MarkupKind::Markdown => self.render(markup.value),
+            lsp_types::MarkupKind::PlainText => markup.value,
+        }
+    }
+
+    fn render_documentation(self, documentation: lsp_types::Documentation) -> String {
+        match documentation {
+            lsp_types::Documentation::String(s) => s,
+            lsp_types::Documentation::MarkupContent(markup) => self.render_markup(markup),
+        }
+    }
+}
+
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct HoverRequest {
     pub file_path: String,
     pub line: u32,
     pub column: u32,
+    #[serde(default)]
+    pub format: DocFormat,
+    /// Return only the type or signature, without documentation
+    #[serde(default)]
+    pub concise: bool,
+    /// Return the hover contents exactly as the server sent them
+    #[serde(default)]
+    pub raw: bool,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionTrigger {
+    /// Completion was requested explicitly (e.g. Ctrl+Space)
+    Invoked,
+    /// Completion was triggered by typing a trigger character such as "." or ":"
+    TriggerCharacter,
+    /// Re-requesting completions because the previous list was incomplete
+    Incomplete,
+}
+
+impl From<CompletionTrigger> for lsp_types::CompletionTriggerKind {
+    fn from(trigger: CompletionTrigger) -> Self {
+        match trigger {
+            CompletionTrigger::Invoked => lsp_types::CompletionTriggerKind::INVOKED,
+            CompletionTrigger::TriggerCharacter => {
+                lsp_types::CompletionTriggerKind::TRIGGER_CHARACTER
+            }
+            CompletionTrigger::Incomplete => {
+                lsp_types::CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS
+            }
+        }
+    }
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -29,6 +124,26 @@ pub struct CompletionRequest {
     pub file_path: String,
     pub line: u32,
     pub column: u32,
+    #[serde(default)]
+    pub format: DocFormat,
+    /// How completion was triggered; defaults to "trigger_character" when a character is given
+    pub trigger_kind: Option<CompletionTrigger>,
+    /// Character that triggered completion, e.g. "." or ":"
+    pub trigger_character: Option<String>,
+    /// Only keep items matching the identifier typed before the position
+    #[serde(default)]
+    pub filter_prefix: bool,
+    /// Keep the server's order and duplicates instead of ranking by sortText
+    #[serde(default)]
+    pub raw_order: bool,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ResolveCompletionRequest {
+    /// Number of the item in the last completion result
+    pub index: usize,
+    #[serde(default)]
+    pub format: DocFormat,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -50,6 +165,42 @@ pub struct FindReferencesRequest {
     pub column: u32,
     #[serde(default = "default_include_declaration")]
     pub include_declaration: bool,
+    /// Show the source line of each reference
+    #[serde(default)]
+    pub preview: bool,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct DefinitionSourceRequest {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SymbolPathRequest {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ExplainSymbolRequest {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct UsageSummaryRequest {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+    #[serde(default = "default_include_declaration")]
+    pub include_declaration: bool,
+    /// Also list each reference's position under its file
+    #[serde(default)]
+    pub include_locations: bool,
 }
 
 fn default_include_declaration() -> bool {
@@ -59,6 +210,39 @@ fn default_include_declaration() -> bool {
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct FormatRequest {
     pub file_path: String,
+    /// Width of a tab in spaces. rust-analyzer formats with rustfmt, which
+    /// ignores this and reads rustfmt.toml instead
+    #[serde(default = "default_tab_size")]
+    pub tab_size: u32,
+    /// Indent with spaces rather than tabs. Also ignored by rustfmt, which
+    /// takes `hard_tabs` from rustfmt.toml
+    #[serde(default = "default_insert_spaces")]
+    pub insert_spaces: bool,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct FormatRangeRequest {
+    pub file_path: String,
+    pub start_line: u32,
+    pub start_column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+    /// Width of a tab in spaces. rust-analyzer formats with rustfmt, which
+    /// ignores this and reads rustfmt.toml instead
+    #[serde(default = "default_tab_size")]
+    pub tab_size: u32,
+    /// Indent with spaces rather than tabs. Also ignored by rustfmt, which
+    /// takes `hard_tabs` from rustfmt.toml
+    #[serde(default = "default_insert_spaces")]
+    pub insert_spaces: bool,
+}
+
+fn default_tab_size() -> u32 {
+    4
+}
+
+fn default_insert_spaces() -> bool {
+    true
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -67,6 +251,17 @@ pub struct RenameRequest {
     pub line: u32,
     pub column: u32,
     pub new_name: String,
+    /// Show a unified diff per affected file instead of listing the edits
+    #[serde(default)]
+    pub diff: bool,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct PrepareAndRenameRequest {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+    pub new_name: String,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -74,16 +269,48 @@ pub struct CodeActionsRequest {
     pub file_path: String,
     pub line: u32,
     pub column: u32,
+    /// Only compute actions of these kinds, e.g. ["quickfix", "refactor.extract"]
+    pub only: Option<Vec<String>>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ApplyCodeActionRequest {
+    /// Number of the action in the last code_actions result
+    pub index: usize,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct FixAllRequest {
+    pub file_path: String,
+    /// Report the fixes without changing any files
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Apply the server's `source.fixAll` action instead of individual quick
+    /// fixes when it offers one
+    #[serde(default)]
+    pub use_fix_all: bool,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct WorkspaceSymbolsRequest {
     pub query: String,
+    /// Only include symbols of these kinds, e.g. ["function", "struct"]
+    pub kinds: Option<Vec<String>>,
+    /// Only include symbols whose file path contains this string, e.g. "src/"
+    pub path_contains: Option<String>,
+    /// Maximum number of symbols to return (default: 20)
+    pub limit: Option<usize>,
+    /// Stop the search after this many milliseconds and return what was found
+    pub timeout_ms: Option<u64>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct InlayHintsRequest {
     pub file_path: String,
+    /// First line to get hints for (0-indexed, default: start of file)
+    pub start_line: Option<u32>,
+    /// Last line to get hints for, inclusive (0-indexed, default: end of file)
+    pub end_line: Option<u32>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -91,11 +318,53 @@ pub struct ExpandMacroRequest {
     pub file_path: String,
     pub line: u32,
     pub column: u32,
+    /// Replace the macro call in the file with its expansion
+    #[serde(default)]
+    pub apply: bool,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct DocumentSymbolsRequest {
     pub file_path: String,
+    /// Only include symbols of these kinds, e.g. ["function", "struct"]
+    pub kinds: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MinSeverity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl MinSeverity {
+    /// Whether a diagnostic of `severity` is at least this severe. Diagnostics
+    /// without a severity are treated as information.
+    fn allows(self, severity: Option<lsp_types::DiagnosticSeverity>) -> bool {
+        let rank = match severity {
+            Some(lsp_types::DiagnosticSeverity::ERROR) => MinSeverity::Error,
+            Some(lsp_types::DiagnosticSeverity::WARNING) => MinSeverity::Warning,
+            Some(lsp_types::DiagnosticSeverity::HINT) => MinSeverity::Hint,
+            _ => MinSeverity::Information,
+        };
+        rank as u8 <= self as u8
+    }
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct BreadcrumbRequest {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct FileOverviewRequest {
+    pub file_path: String,
+    /// Only include diagnostics at least this severe: "error", "warning", "information" or "hint"
+    pub min_severity: Option<MinSeverity>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -103,6 +372,12 @@ pub struct SignatureHelpRequest {
     pub file_path: String,
     pub line: u32,
     pub column: u32,
+    /// Character that triggered the request, e.g. "(" or ","
+    pub trigger_character: Option<String>,
+    /// Whether signature help was already showing when triggered
+    pub is_retrigger: Option<bool>,
+    #[serde(default)]
+    pub format: DocFormat,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -116,6 +391,9 @@ pub struct DocumentHighlightRequest {
 pub struct SelectionRangeRequest {
     pub file_path: String,
     pub positions: Vec<PositionInfo>,
+    /// Include the source text each level selects
+    #[serde(default)]
+    pub include_text: bool,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -123,6 +401,11 @@ pub struct RunnablesRequest {
     pub file_path: String,
 }
 
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ListImplsRequest {
+    pub file_path: String,
+}
+
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct ImplementationsRequest {
     pub file_path: String,
@@ -131,191 +414,1403 @@ pub struct ImplementationsRequest {
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
-pub struct PositionInfo {
+pub struct DocumentLinksRequest {
+    pub file_path: String,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct CodeLensesRequest {
+    pub file_path: String,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ViewItemTreeRequest {
+    pub file_path: String,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ViewFileTextRequest {
+    pub file_path: String,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct InterpretFunctionRequest {
+    pub file_path: String,
     pub line: u32,
     pub column: u32,
 }
 
-#[derive(Clone)]
-pub struct RustAnalyzerMCP {
-    lsp_client: Arc<Mutex<LspClient>>,
-    workspace_root: PathBuf,
-    tool_router: ToolRouter<RustAnalyzerMCP>,
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ConfigureRequest {
+    /// Language server settings to merge into the current ones, e.g. {"procMacro": {"enable": false}}
+    pub settings: serde_json::Value,
 }
 
-#[tool_router]
-impl RustAnalyzerMCP {
-    pub async fn new(workspace_root: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
-        info!("Initializing rust-analyzer MCP server for workspace: {:?}", workspace_root);
-        let lsp_client = LspClient::new(&workspace_root).await?;
-        info!("rust-analyzer LSP client initialized and ready");
-        Ok(Self {
-            lsp_client: Arc::new(Mutex::new(lsp_client)),
-            workspace_root,
-            tool_router: Self::tool_router(),
-        })
-    }
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct LspRawRequest {
+    /// LSP method, e.g. "rust-analyzer/viewHir" or "textDocument/hover"
+    pub method: String,
+    /// Request params, passed through unchanged
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
 
-    #[tool(description = "Get type information and documentation at a specific position")]
-    async fn hover(
-        &self,
-        Parameters(request): Parameters<HoverRequest>,
-    ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct PingRequest {
+    /// How long to wait for an answer, in milliseconds (default 5000)
+    #[serde(default = "default_ping_timeout_ms")]
+    pub timeout_ms: u64,
+}
 
-        match lsp_client
-            .hover(&request.file_path, request.line, request.column)
-            .await
-        {
-            Ok(Some(hover)) => {
-                let content = match hover.contents {
-                    lsp_types::HoverContents::Markup(markup) => markup.value,
-                    lsp_types::HoverContents::Array(markups) => markups
-                        .into_iter()
-                        .map(|m| match m {
-                            lsp_types::MarkedString::String(s) => s,
-                            lsp_types::MarkedString::LanguageString(ls) => ls.value,
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n\n"),
-                    lsp_types::HoverContents::Scalar(ms) => match ms {
-                        lsp_types::MarkedString::String(s) => s,
-                        lsp_types::MarkedString::LanguageString(ls) => ls.value,
-                    },
-                };
-                Ok(CallToolResult::success(vec![Content::text(content)]))
-            }
-            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
-                "No hover information available",
-            )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
-        }
-    }
+fn default_ping_timeout_ms() -> u64 {
+    5000
+}
 
-    #[tool(description = "Get code completions at a specific position")]
-    async fn completion(
-        &self,
-        Parameters(request): Parameters<CompletionRequest>,
-    ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct MonikerRequest {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+}
 
-        match lsp_client
-            .completion(&request.file_path, request.line, request.column)
-            .await
-        {
-            Ok(Some(result)) => {
-                let completions = match result {
-                    lsp_types::CompletionResponse::Array(items) => items,
-                    lsp_types::CompletionResponse::List(list) => list.items,
-                };
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ServerCapabilitiesRequest {}
 
-                let completion_text = completions
-                    .into_iter()
-                    .take(10) // Limit to first 10 for readability
-                    .map(|item| {
-                        let detail = item.detail.unwrap_or_default();
-                        let doc = item
-                            .documentation
-                            .map(|d| match d {
-                                lsp_types::Documentation::String(s) => s,
-                                lsp_types::Documentation::MarkupContent(mc) => mc.value,
-                            })
-                            .unwrap_or_default();
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ListCommandsRequest {}
 
-                        if doc.is_empty() {
-                            format!("- {}: {}", item.label, detail)
-                        } else {
-                            format!("- {}: {} - {}", item.label, detail, doc)
-                        }
-                    })
-                    .collect::<Vec<_>>()
-                    .join("\n");
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ExecuteCommandRequest {
+    /// Command id, as returned by list_commands
+    pub command: String,
+    /// Arguments passed to the command unchanged
+    #[serde(default)]
+    pub arguments: Vec<serde_json::Value>,
+}
 
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Completions:\n{}",
-                    completion_text
-                ))]))
-            }
-            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
-                "No completions available",
-            )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
-        }
-    }
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ServerStatusRequest {}
 
-    #[tool(description = "Get compile errors and warnings for a file")]
-    async fn diagnostics(
-        &self,
-        Parameters(request): Parameters<DiagnosticsRequest>,
-    ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct CloseDocumentRequest {
+    pub file_path: String,
+}
 
-        match lsp_client.diagnostics(&request.file_path).await {
-            Ok(diagnostics) => {
-                if diagnostics.is_empty() {
-                    Ok(CallToolResult::success(vec![Content::text(
-                        "No diagnostics found",
-                    )]))
-                } else {
-                    let diagnostic_text = diagnostics
-                        .into_iter()
-                        .map(|diag| {
-                            let severity = diag
-                                .severity
-                                .map(|s| format!("{:?}", s))
-                                .unwrap_or("Info".to_string());
-                            let range = format!(
-                                "{}:{}-{}:{}",
-                                diag.range.start.line,
-                                diag.range.start.character,
-                                diag.range.end.line,
-                                diag.range.end.character
-                            );
-                            format!(
-                                "[{}] {}: {} ({})",
-                                severity,
-                                range,
-                                diag.message,
-                                diag.source.unwrap_or_default()
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n");
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct PositionInfo {
+    pub line: u32,
+    pub column: u32,
+}
 
-                    Ok(CallToolResult::success(vec![Content::text(format!(
-                        "Diagnostics:\n{}",
-                        diagnostic_text
-                    ))]))
-                }
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct RangeInfo {
+    pub start: PositionInfo,
+    pub end: PositionInfo,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct TextEditInfo {
+    pub range: RangeInfo,
+    pub new_text: String,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ApplyEditsRequest {
+    pub file_path: String,
+    /// Edits against the file's current text, with 0-indexed positions
+    pub edits: Vec<TextEditInfo>,
+}
+
+/// Match a symbol kind against user-supplied kind names such as "function" or
+/// "enum_member". An absent filter matches everything.
+fn symbol_kind_matches(kinds: &Option<Vec<String>>, kind: lsp_types::SymbolKind) -> bool {
+    let Some(kinds) = kinds else {
+        return true;
+    };
+    let normalize = |name: &str| name.replace(['_', ' '], "").to_lowercase();
+    let kind_name = normalize(&format!("{:?}", kind));
+    kinds.iter().any(|k| normalize(k) == kind_name)
+}
+
+/// Parse user-supplied code action kinds. Kinds are hierarchical, so any
+/// sub-kind of a standard kind such as "refactor.extract.function" is accepted.
+fn parse_code_action_kinds(kinds: Vec<String>) -> Result<Vec<lsp_types::CodeActionKind>, McpError> {
+    const BASE_KINDS: &[&str] = &["quickfix", "refactor", "source"];
+    kinds
+        .into_iter()
+        .map(|kind| {
+            let base = kind.split('.').next().unwrap_or_default();
+            if BASE_KINDS.contains(&base) && !kind.split('.').any(str::is_empty) {
+                Ok(lsp_types::CodeActionKind::from(kind))
+            } else {
+                Err(McpError::invalid_params(
+                    format!(
+                        "Unknown code action kind: {:?}; expected quickfix, refactor or source, optionally with a sub-kind such as refactor.extract",
+                        kind
+                    ),
+                    None,
+                ))
             }
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+        })
+        .collect()
+}
+
+/// The type or signature of a hover, without its documentation.
+fn hover_signature(contents: &lsp_types::HoverContents) -> String {
+    let markdown = hover_markdown(contents);
+    markdown::primary_code_block(&markdown).unwrap_or_else(|| {
+        let plaintext = markdown::to_plaintext(&markdown);
+        plaintext.lines().next().unwrap_or_default().to_string()
+    })
+}
+
+fn hover_markdown(contents: &lsp_types::HoverContents) -> String {
+    let to_markdown = |ms: &lsp_types::MarkedString| match ms {
+        lsp_types::MarkedString::String(s) => s.clone(),
+        lsp_types::MarkedString::LanguageString(ls) => {
+            format!("```{}\n{}\n```", ls.language, ls.value)
         }
+    };
+    match contents {
+        lsp_types::HoverContents::Markup(markup) => markup.value.clone(),
+        lsp_types::HoverContents::Scalar(ms) => to_markdown(ms),
+        lsp_types::HoverContents::Array(markups) => markups
+            .iter()
+            .map(to_markdown)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
     }
+}
 
-    #[tool(description = "Find definition of symbol at position")]
-    async fn goto_definition(
+fn format_hover_range(range: lsp_types::Range) -> String {
+    format!("\n\nRange: {}", display_range(range))
+}
+
+/// A position as 1-based `line:column`, the convention for all tool output.
+/// Tool inputs stay 0-based like LSP itself.
+fn display_position(position: lsp_types::Position) -> String {
+    format!("{}:{}", position.line + 1, position.character + 1)
+}
+
+/// A range as 1-based `line:column-line:column`.
+fn display_range(range: lsp_types::Range) -> String {
+    format!(
+        "{}-{}",
+        display_position(range.start),
+        display_position(range.end)
+    )
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Byte offset where the identifier ending at byte `end` of `line` starts.
+fn identifier_start(line: &str, end: usize) -> usize {
+    line[..end]
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| !is_identifier_char(c))
+        .map_or(0, |(i, c)| i + c.len_utf8())
+}
+
+/// The identifier touching UTF-16 `column` on `line`.
+fn identifier_at(line: &str, column: u32) -> String {
+    let column = position::column_offset(line, column).unwrap_or(line.len());
+    let end = line[column..]
+        .find(|c| !is_identifier_char(c))
+        .map_or(line.len(), |i| column + i);
+    line[identifier_start(line, column)..end].to_string()
+}
+
+/// The identifier characters directly before UTF-16 `column` on `line`.
+fn identifier_prefix(line: &str, column: u32) -> String {
+    let column = position::column_offset(line, column).unwrap_or(line.len());
+    line[identifier_start(line, column)..column].to_string()
+}
+
+/// Order completions the way an editor would: by `sortText` (falling back to
+/// the label), dropping duplicate label/detail pairs and, given a prefix,
+/// items whose `filterText` doesn't start with it.
+fn rank_completions(
+    mut items: Vec<lsp_types::CompletionItem>,
+    prefix: Option<&str>,
+) -> Vec<lsp_types::CompletionItem> {
+    if let Some(prefix) = prefix.map(str::to_lowercase) {
+        items.retain(|item| {
+            let text = item.filter_text.as_deref().unwrap_or(&item.label);
+            text.to_lowercase().starts_with(&prefix)
+        });
+    }
+
+    // Stable sort keeps the server's order among equal keys
+    items.sort_by(|a, b| {
+        let key = |item: &lsp_types::CompletionItem| {
+            item.sort_text.clone().unwrap_or_else(|| item.label.clone())
+        };
+        key(a).cmp(&key(b))
+    });
+
+    let mut seen = std::collections::HashSet::new();
+    items.retain(|item| seen.insert((item.label.clone(), item.detail.clone())));
+    items
+}
+
+/// The text a completion inserts, preferring its edit over `insertText`.
+fn completion_insert_text(item: &lsp_types::CompletionItem) -> Option<&str> {
+    match &item.text_edit {
+        Some(lsp_types::CompletionTextEdit::Edit(edit)) => Some(&edit.new_text),
+        Some(lsp_types::CompletionTextEdit::InsertAndReplace(edit)) => Some(&edit.new_text),
+        None => item.insert_text.as_deref(),
+    }
+}
+
+/// A snippet's text with placeholders filled in by their defaults, and its
+/// numbered placeholders in order with their default text. The final cursor
+/// position `$0` is dropped.
+fn expand_snippet(snippet: &str) -> (String, Vec<(u32, String)>) {
+    type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+    fn number(chars: &mut Chars) -> Option<u32> {
+        let mut digits = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+            digits.push(c);
+        }
+        digits.parse().ok()
+    }
+
+    fn parse(
+        chars: &mut Chars,
+        out: &mut String,
+        placeholders: &mut Vec<(u32, String)>,
+        nested: bool,
+    ) {
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next_if(|c| matches!(c, '$' | '}' | '\\')) {
+                    Some(escaped) => out.push(escaped),
+                    None => out.push('\\'),
+                },
+                '}' if nested => return,
+                '$' if chars.peek().is_some_and(|c| c.is_ascii_digit()) => {
+                    if let Some(index) = number(chars) {
+                        placeholders.push((index, String::new()));
+                    }
+                }
+                '$' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    let index = number(chars);
+                    let mut default = String::new();
+                    match chars.next() {
+                        Some(':') => parse(chars, &mut default, placeholders, true),
+                        // A choice defaults to its first option
+                        Some('|') => {
+                            let choices: String =
+                                chars.by_ref().take_while(|c| *c != '|').collect();
+                            default = choices.split(',').next().unwrap_or_default().to_string();
+                            chars.next_if_eq(&'}');
+                        }
+                        _ => {}
+                    }
+                    out.push_str(&default);
+                    if let Some(index) = index {
+                        placeholders.push((index, default));
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    let mut text = String::new();
+    let mut placeholders = Vec::new();
+    let mut chars = snippet.chars().peekable();
+    parse(&mut chars, &mut text, &mut placeholders, false);
+
+    // Keep the first occurrence of each placeholder, in tab order
+    placeholders.retain(|(index, _)| *index != 0);
+    placeholders.sort_by_key(|(index, _)| *index);
+    placeholders.dedup_by_key(|(index, _)| *index);
+    (text, placeholders)
+}
+
+/// Placeholders in tab order, each with its default text when it has one.
+fn format_placeholders(placeholders: &[(u32, String)]) -> String {
+    placeholders
+        .iter()
+        .map(|(index, default)| {
+            if default.is_empty() {
+                format!("${}", index)
+            } else {
+                format!("${} `{}`", index, default)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn is_snippet(item: &lsp_types::CompletionItem) -> bool {
+    item.insert_text_format == Some(lsp_types::InsertTextFormat::SNIPPET)
+}
+
+fn format_document_symbols(
+    response: lsp_types::DocumentSymbolResponse,
+    kinds: &Option<Vec<String>>,
+) -> String {
+    use lsp_types::DocumentSymbolResponse;
+    match response {
+        DocumentSymbolResponse::Flat(symbols) => symbols
+            .into_iter()
+            .filter(|symbol| symbol_kind_matches(kinds, symbol.kind))
+            .map(|symbol| {
+                let location = &symbol.location;
+                let file_path = location
+                    .uri
+                    .to_file_path()
+                    .ok()
+                    .and_then(|p| p.to_str().map(|s| s.to_string()))
+                    .unwrap_or_else(|| location.uri.to_string());
+                let kind = format!("{:?}", symbol.kind);
+                #[allow(deprecated)]
+                let deprecated = deprecated_marker(&symbol.tags, symbol.deprecated);
+                let container = symbol
+                    .container_name
+                    .map(|c| format!(" (in {})", c))
+                    .unwrap_or_default();
+
+                format!(
+                    "• {} [{}]{}: {}:{}{}",
+                    symbol.name,
+                    kind,
+                    deprecated,
+                    file_path,
+                    display_position(location.range.start),
+                    container
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        DocumentSymbolResponse::Nested(symbols) => {
+            fn format_nested_symbols(
+                symbols: Vec<lsp_types::DocumentSymbol>,
+                indent: usize,
+                kinds: &Option<Vec<String>>,
+                lines: &mut Vec<String>,
+            ) {
+                for symbol in symbols {
+                    let matches = symbol_kind_matches(kinds, symbol.kind);
+                    if matches {
+                        let indent_str = "  ".repeat(indent);
+                        let kind = format!("{:?}", symbol.kind);
+                        #[allow(deprecated)]
+                        let deprecated = deprecated_marker(&symbol.tags, symbol.deprecated);
+                        let detail = symbol
+                            .detail
+                            .as_ref()
+                            .filter(|d| !d.is_empty())
+                            .map(|d| format!(" - {}", d))
+                            .unwrap_or_default();
+                        let range = &symbol.range;
+                        lines.push(format!(
+                            "{}• {} [{}]{}: line {}{}",
+                            indent_str,
+                            symbol.name,
+                            kind,
+                            deprecated,
+                            display_position(range.start),
+                            detail
+                        ));
+                    }
+
+                    if let Some(children) = symbol.children {
+                        // Children of filtered-out symbols move up to their parent's level
+                        let child_indent = if matches { indent + 1 } else { indent };
+                        format_nested_symbols(children, child_indent, kinds, lines);
+                    }
+                }
+            }
+            let mut lines = Vec::new();
+            format_nested_symbols(symbols, 0, kinds, &mut lines);
+            lines.join("\n")
+        }
+    }
+}
+
+/// Split an impl block's symbol name, e.g. `impl<T> Greetable for User<T>`,
+/// into its optional trait and its self type.
+fn parse_impl_header(name: &str) -> Option<(Option<String>, String)> {
+    let mut header = name.strip_prefix("impl")?;
+    if !header.starts_with([' ', '<']) {
+        return None;
+    }
+    if header.starts_with('<') {
+        // Skip the impl's own generic parameters
+        let mut depth = 0;
+        let end = header.char_indices().find_map(|(i, c)| {
+            match c {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(i + 1)
+        })?;
+        header = &header[end..];
+    }
+    let header = header.trim();
+    match header.split_once(" for ") {
+        Some((trait_name, self_ty)) => Some((
+            Some(trait_name.trim().to_string()),
+            self_ty.trim().to_string(),
+        )),
+        None if !header.is_empty() => Some((None, header.to_string())),
+        None => None,
+    }
+}
+
+/// Flatten a document symbol response into (name, kind, position) triples,
+/// using each symbol's name position where the server provides one.
+fn flatten_document_symbols(
+    response: lsp_types::DocumentSymbolResponse,
+) -> Vec<(String, lsp_types::SymbolKind, lsp_types::Position)> {
+    fn walk(
+        symbols: Vec<lsp_types::DocumentSymbol>,
+        out: &mut Vec<(String, lsp_types::SymbolKind, lsp_types::Position)>,
+    ) {
+        for symbol in symbols {
+            out.push((symbol.name, symbol.kind, symbol.selection_range.start));
+            if let Some(children) = symbol.children {
+                walk(children, out);
+            }
+        }
+    }
+
+    match response {
+        lsp_types::DocumentSymbolResponse::Flat(symbols) => symbols
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.kind, symbol.location.range.start))
+            .collect(),
+        lsp_types::DocumentSymbolResponse::Nested(symbols) => {
+            let mut out = Vec::new();
+            walk(symbols, &mut out);
+            out
+        }
+    }
+}
+
+/// Definition targets as plain locations pointing at each item's name.
+fn definition_locations(response: lsp_types::GotoDefinitionResponse) -> Vec<lsp_types::Location> {
+    use lsp_types::GotoDefinitionResponse;
+    match response {
+        GotoDefinitionResponse::Scalar(location) => vec![location],
+        GotoDefinitionResponse::Array(locations) => locations,
+        GotoDefinitionResponse::Link(links) => links
+            .into_iter()
+            .map(|link| lsp_types::Location {
+                uri: link.target_uri,
+                range: link.target_selection_range,
+            })
+            .collect(),
+    }
+}
+
+/// The full range of the innermost symbol containing `position`.
+fn enclosing_symbol_range(
+    response: lsp_types::DocumentSymbolResponse,
+    position: lsp_types::Position,
+) -> Option<lsp_types::Range> {
+    fn innermost(
+        symbols: &[lsp_types::DocumentSymbol],
+        position: lsp_types::Position,
+    ) -> Option<lsp_types::Range> {
+        let symbol = symbols
+            .iter()
+            .find(|s| s.range.start <= position && position <= s.range.end)?;
+        symbol
+            .children
+            .as_deref()
+            .and_then(|children| innermost(children, position))
+            .or(Some(symbol.range))
+    }
+
+    match response {
+        lsp_types::DocumentSymbolResponse::Nested(symbols) => innermost(&symbols, position),
+        // Flat symbols don't nest, so take the smallest containing range
+        lsp_types::DocumentSymbolResponse::Flat(symbols) => symbols
+            .into_iter()
+            .map(|symbol| symbol.location.range)
+            .filter(|range| range.start <= position && position <= range.end)
+            .min_by_key(|range| (range.end.line - range.start.line, range.end.character)),
+    }
+}
+
+/// Names of the symbols enclosing `position`, outermost first.
+fn breadcrumb(
+    response: lsp_types::DocumentSymbolResponse,
+    position: lsp_types::Position,
+) -> Vec<String> {
+    let contains = |range: &lsp_types::Range| range.start <= position && position <= range.end;
+
+    match response {
+        lsp_types::DocumentSymbolResponse::Nested(symbols) => {
+            let mut names = Vec::new();
+            let mut symbols = symbols.as_slice();
+            while let Some(symbol) = symbols.iter().find(|s| contains(&s.range)) {
+                names.push(symbol.name.clone());
+                match &symbol.children {
+                    Some(children) => symbols = children,
+                    None => break,
+                }
+            }
+            names
+        }
+        // Flat symbols don't nest, so order the containing ones by size
+        lsp_types::DocumentSymbolResponse::Flat(symbols) => {
+            let mut containing = symbols
+                .into_iter()
+                .filter(|symbol| contains(&symbol.location.range))
+                .collect::<Vec<_>>();
+            containing.sort_by_key(|symbol| {
+                let range = symbol.location.range;
+                (range.start, std::cmp::Reverse(range.end))
+            });
+            containing.into_iter().map(|symbol| symbol.name).collect()
+        }
+    }
+}
+
+/// The directory of the Cargo package containing `path`.
+fn package_root(path: &std::path::Path) -> Option<&std::path::Path> {
+    path.ancestors()
+        .skip(1)
+        .find(|dir| dir.join("Cargo.toml").is_file())
+}
+
+/// Best-effort module path of a source file from the conventional layout under
+/// its package's `src` directory, e.g. `src/a/b.rs` becomes `crate::a::b`.
+fn module_path_from_file(path: &std::path::Path) -> Option<String> {
+    let relative = path.strip_prefix(package_root(path)?.join("src")).ok()?;
+    let components = relative
+        .with_extension("")
+        .iter()
+        .map(|c| c.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+
+    let mut segments = vec!["crate".to_string()];
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i + 1 == components.len();
+        let is_root = i == 0 && (component == "lib" || component == "main");
+        if is_last && (component == "mod" || is_root) {
+            continue;
+        }
+        segments.push(component.clone());
+    }
+    Some(segments.join("::"))
+}
+
+/// Path segments contributed by the symbols enclosing `position`, outermost
+/// first: inline modules, types, and the self type of impl blocks.
+fn enclosing_path_segments(
+    mut symbols: &[lsp_types::DocumentSymbol],
+    position: lsp_types::Position,
+) -> Vec<String> {
+    let contains = |range: &lsp_types::Range| range.start <= position && position <= range.end;
+
+    let mut segments = Vec::new();
+    while let Some(symbol) = symbols.iter().find(|s| contains(&s.range)) {
+        // Reached the symbol itself
+        if contains(&symbol.selection_range) {
+            break;
+        }
+        match symbol.kind {
+            lsp_types::SymbolKind::MODULE
+            | lsp_types::SymbolKind::STRUCT
+            | lsp_types::SymbolKind::ENUM
+            | lsp_types::SymbolKind::INTERFACE => segments.push(symbol.name.clone()),
+            _ => {
+                if let Some((_, self_ty)) = parse_impl_header(&symbol.name) {
+                    let self_ty = self_ty.split('<').next().unwrap_or_default();
+                    segments.push(self_ty.trim().to_string());
+                }
+            }
+        }
+        match &symbol.children {
+            Some(children) => symbols = children,
+            None => break,
+        }
+    }
+    segments
+}
+
+fn format_diagnostic(diag: lsp_types::Diagnostic) -> String {
+    let severity = diag
+        .severity
+        .map(|s| format!("{:?}", s))
+        .unwrap_or("Info".to_string());
+    let range = display_range(diag.range);
+    format!(
+        "[{}] {}: {} ({})",
+        severity,
+        range,
+        diag.message,
+        diag.source.unwrap_or_default()
+    )
+}
+
+/// Collect the text edits of a workspace edit by file path, sorted by position.
+/// Resource operations (create/rename/delete) carry no text edits and are skipped.
+fn text_edits_by_file(
+    edit: lsp_types::WorkspaceEdit,
+) -> BTreeMap<String, Vec<lsp_types::TextEdit>> {
+    use lsp_types::{DocumentChangeOperation, DocumentChanges, OneOf};
+
+    let path_of = |uri: &lsp_types::Url| {
+        uri.to_file_path()
+            .ok()
+            .and_then(|p| p.to_str().map(|s| s.to_string()))
+            .unwrap_or_else(|| uri.to_string())
+    };
+
+    let mut by_file: BTreeMap<String, Vec<lsp_types::TextEdit>> = BTreeMap::new();
+    for (uri, edits) in edit.changes.unwrap_or_default() {
+        by_file.entry(path_of(&uri)).or_default().extend(edits);
+    }
+
+    let document_edits = match edit.document_changes {
+        Some(DocumentChanges::Edits(edits)) => edits,
+        Some(DocumentChanges::Operations(ops)) => ops
+            .into_iter()
+            .filter_map(|op| match op {
+                DocumentChangeOperation::Edit(edit) => Some(edit),
+                DocumentChangeOperation::Op(_) => None,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    for edit in document_edits {
+        let edits = edit.edits.into_iter().map(|edit| match edit {
+            OneOf::Left(edit) => edit,
+            OneOf::Right(annotated) => annotated.text_edit,
+        });
+        by_file
+            .entry(path_of(&edit.text_document.uri))
+            .or_default()
+            .extend(edits);
+    }
+
+    for edits in by_file.values_mut() {
+        edits.sort_by_key(|edit| (edit.range.start.line, edit.range.start.character));
+    }
+    by_file
+}
+
+/// The text covered by `range`, which may span several lines.
+fn range_text(text: &str, range: lsp_types::Range) -> Option<String> {
+    let start = byte_offset(text, range.start)?;
+    let end = byte_offset(text, range.end)?;
+    text.get(start..end).map(|s| s.to_string())
+}
+
+/// Byte offset of `position` in `text`, clamping the column to its line.
+fn byte_offset(text: &str, position: lsp_types::Position) -> Option<usize> {
+    let line_start = position::offset_at(
+        text,
+        lsp_types::Position {
+            line: position.line,
+            character: 0,
+        },
+    )?;
+    let line = text[line_start..].lines().next().unwrap_or_default();
+    let column = position::column_offset(line, position.character).unwrap_or(line.len());
+    Some(line_start + column)
+}
+
+/// Byte offset of `position` in `text`, or `None` if the line or column is
+/// past the end.
+fn checked_byte_offset(text: &str, position: &PositionInfo) -> Option<usize> {
+    position::offset_at(
+        text,
+        lsp_types::Position {
+            line: position.line,
+            character: position.column,
+        },
+    )
+}
+
+fn text_edit_info(edit: &lsp_types::TextEdit) -> TextEditInfo {
+    TextEditInfo {
+        range: RangeInfo {
+            start: PositionInfo {
+                line: edit.range.start.line,
+                column: edit.range.start.character,
+            },
+            end: PositionInfo {
+                line: edit.range.end.line,
+                column: edit.range.end.character,
+            },
+        },
+        new_text: edit.new_text.clone(),
+    }
+}
+
+/// Write the text edits of a workspace edit to disk, returning the changed
+/// paths. Every file is checked before any is written, so an edit that doesn't
+/// apply leaves all files untouched.
+async fn apply_workspace_edit(
+    lsp_client: &LspClient,
+    edit: lsp_types::WorkspaceEdit,
+) -> Result<Vec<String>, McpError> {
+    let mut new_texts = Vec::new();
+    for (path, edits) in text_edits_by_file(edit) {
+        let text = lsp_client.read_file(&path).await?;
+        let infos = edits.iter().map(text_edit_info).collect();
+        let new_text = apply_text_edits(&text, infos).map_err(|e| {
+            McpError::invalid_params(format!("Cannot apply edit to {}: {}", path, e), None)
+        })?;
+        new_texts.push((path, new_text));
+    }
+
+    for (path, text) in &new_texts {
+        lsp_client.write_file(path, text).await?;
+    }
+    Ok(new_texts.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Apply `edits` to `text`. Every range must lie within the text, and no two
+/// edits may overlap.
+fn apply_text_edits(text: &str, edits: Vec<TextEditInfo>) -> Result<String, String> {
+    let mut spans = edits
+        .into_iter()
+        .enumerate()
+        .map(|(i, edit)| {
+            let offset = |position: &PositionInfo| {
+                checked_byte_offset(text, position).ok_or_else(|| {
+                    format!(
+                        "edit {}: position {}:{} is outside the file",
+                        i + 1,
+                        position.line,
+                        position.column
+                    )
+                })
+            };
+            let start = offset(&edit.range.start)?;
+            let end = offset(&edit.range.end)?;
+            if end < start {
+                return Err(format!("edit {}: range ends before it starts", i + 1));
+            }
+            Ok((start, end, i, edit.new_text))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // Sorting is stable, so insertions at one position keep their given order
+    spans.sort_by_key(|(start, end, _, _)| (*start, *end));
+    for pair in spans.windows(2) {
+        let (_, previous_end, previous, _) = &pair[0];
+        let (start, _, current, _) = &pair[1];
+        if start < previous_end {
+            return Err(format!(
+                "edits {} and {} overlap",
+                previous.min(current) + 1,
+                previous.max(current) + 1
+            ));
+        }
+    }
+
+    // Splice from the end so earlier offsets stay valid
+    let mut result = text.to_string();
+    for (start, end, _, new_text) in spans.into_iter().rev() {
+        result.replace_range(start..end, &new_text);
+    }
+    Ok(result)
+}
+
+/// The range of the `name!` invocation around a position: the innermost
+/// selection level that spans a whole call, from the macro path to its
+/// closing bracket.
+fn macro_call_range(
+    source: &str,
+    mut selection: lsp_types::SelectionRange,
+    name: &str,
+) -> Option<lsp_types::Range> {
+    let bang = format!("{}!", name);
+    let is_call = |text: &str| {
+        let Some((path, args)) = text.split_once(&bang) else {
+            return false;
+        };
+        // An optional `std::`-style path before the name, then the bracketed input
+        let is_path_char = |c: char| c.is_alphanumeric() || c == '_' || c == ':';
+        let qualified = path.is_empty() || (path.ends_with("::") && path.chars().all(is_path_char));
+        qualified && args.starts_with(['(', '[', '{']) && args.ends_with([')', ']', '}'])
+    };
+
+    loop {
+        if range_text(source, selection.range).is_some_and(|text| is_call(text.trim())) {
+            return Some(selection.range);
+        }
+        selection = *selection.parent?;
+    }
+}
+
+/// Indent `text` under a selection level, keeping at most
+/// `SELECTION_TEXT_MAX_LINES` lines.
+fn format_selection_text(text: &str, indent: &str) -> String {
+    let lines = text.lines().collect::<Vec<_>>();
+    let mut out = lines
+        .iter()
+        .take(SELECTION_TEXT_MAX_LINES)
+        .map(|line| format!("\n{}  | {}", indent, line))
+        .collect::<String>();
+    if lines.len() > SELECTION_TEXT_MAX_LINES {
+        out.push_str(&format!(
+            "\n{}  | … ({} more lines)",
+            indent,
+            lines.len() - SELECTION_TEXT_MAX_LINES
+        ));
+    }
+    out
+}
+
+/// Result for tools built on rust-analyzer extensions when another server is
+/// driving the bridge.
+fn unsupported_by_backend(tool: &str) -> CallToolResult {
+    CallToolResult::success(vec![Content::text(format!(
+        "'{}' relies on a rust-analyzer extension and is unsupported by this backend",
+        tool
+    ))])
+}
+
+fn deprecated_marker(
+    tags: &Option<Vec<lsp_types::SymbolTag>>,
+    deprecated: Option<bool>,
+) -> &'static str {
+    let tagged = tags
+        .as_ref()
+        .is_some_and(|tags| tags.contains(&lsp_types::SymbolTag::DEPRECATED));
+    if tagged || deprecated == Some(true) {
+        " [deprecated]"
+    } else {
+        ""
+    }
+}
+
+/// Build a `cargo ...` command line from a rust-analyzer runnable's `args.cargoArgs`.
+fn runnable_cargo_command(runnable: &serde_json::Value) -> Option<String> {
+    let parts: Vec<&str> = runnable
+        .get("args")?
+        .get("cargoArgs")?
+        .as_array()?
+        .iter()
+        .filter_map(|v| v.as_str())
+        .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(format!("cargo {}", parts.join(" ")))
+    }
+}
+
+/// One piece of server work as "title: message (percentage%)".
+fn describe_work(work: &lsp_client::WorkProgress) -> String {
+    let message = work
+        .message
+        .as_ref()
+        .map(|m| format!(": {}", m))
+        .unwrap_or_default();
+    let percentage = work
+        .percentage
+        .map(|p| format!(" ({}%)", p))
+        .unwrap_or_default();
+    format!("{}{}{}", work.title, message, percentage)
+}
+
+/// "ready", or "indexing" while the server reports work with `$/progress`.
+fn work_status(progress: &[lsp_client::WorkProgress]) -> String {
+    if progress.is_empty() {
+        return "ready".to_string();
+    }
+    let work: Vec<String> = progress.iter().map(describe_work).collect();
+    format!("indexing ({})", work.join(", "))
+}
+
+/// Lines shown either side of a definition that isn't inside any item.
+const DEFINITION_CONTEXT_LINES: u32 = 3;
+
+/// Lines of each file's diff shown by `rename` with `diff`.
+const RENAME_DIFF_MAX_LINES: usize = 200;
+
+/// Lines of source shown per level by `selection_range` with `include_text`.
+const SELECTION_TEXT_MAX_LINES: usize = 12;
+
+/// LSP capabilities relevant to the tools, as (capability key, tool name) pairs.
+const CAPABILITY_TOOLS: &[(&str, Option<&str>)] = &[
+    ("hoverProvider", Some("hover")),
+    ("completionProvider", Some("completion")),
+    ("diagnosticProvider", Some("diagnostics")),
+    ("definitionProvider", Some("goto_definition")),
+    ("referencesProvider", Some("find_references")),
+    ("documentFormattingProvider", Some("format_document")),
+    ("documentRangeFormattingProvider", Some("format_range")),
+    ("renameProvider", Some("rename")),
+    ("codeActionProvider", Some("code_actions")),
+    ("workspaceSymbolProvider", Some("workspace_symbols")),
+    ("inlayHintProvider", Some("inlay_hints")),
+    ("documentSymbolProvider", Some("document_symbols")),
+    ("signatureHelpProvider", Some("signature_help")),
+    ("documentHighlightProvider", Some("document_highlight")),
+    ("selectionRangeProvider", Some("selection_range")),
+    ("implementationProvider", Some("implementations")),
+    ("documentLinkProvider", Some("document_links")),
+    ("codeLensProvider", Some("code_lenses")),
+    ("monikerProvider", Some("moniker")),
+    ("executeCommandProvider", Some("execute_command")),
+    ("typeDefinitionProvider", None),
+    ("callHierarchyProvider", None),
+    ("semanticTokensProvider", None),
+];
+
+/// Status reported while the language server is shut down for being idle.
+const IDLE_STOPPED_STATUS: &str =
+    "Status: stopped after being idle; the language server starts again on the next request";
+
+/// How often the idle watcher checks for inactivity, at most.
+const IDLE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// What the last `completion` and `code_actions` calls of one MCP session
+/// listed, numbered as shown.
+#[derive(Default)]
+struct ListedItems {
+    /// Kept whole so their `data` goes back to the server unchanged on resolve.
+    completions: Vec<lsp_types::CompletionItem>,
+    code_actions: Vec<lsp_types::CodeActionOrCommand>,
+}
+
+#[derive(Clone)]
+pub struct RustAnalyzerMCP {
+    /// `None` while the language server is shut down for being idle. Tool
+    /// calls each hold a clone, so they run side by side.
+    lsp_client: Arc<Mutex<Option<Arc<LspClient>>>>,
+    /// When a tool last used the language server.
+    last_activity: Arc<Mutex<std::time::Instant>>,
+    /// Settings of a server shut down for being idle, sent again as its
+    /// initialization options when it restarts.
+    stopped_settings: Arc<Mutex<Option<serde_json::Value>>>,
+    config: Arc<Config>,
+    workspace_roots: Vec<PathBuf>,
+    /// This session's listed items; every SSE connection gets its own.
+    listed_items: Arc<Mutex<ListedItems>>,
+    /// The listed items of every live session, cleared when the server stops.
+    /// A std mutex, as sessions are registered outside async code.
+    sessions: Arc<std::sync::Mutex<Vec<Weak<Mutex<ListedItems>>>>>,
+    tool_router: ToolRouter<RustAnalyzerMCP>,
+}
+
+#[tool_router]
+impl RustAnalyzerMCP {
+    pub async fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let workspace_roots = config.workspace_roots.clone();
+        info!(
+            "Initializing rust-analyzer MCP server for workspaces: {:?}",
+            workspace_roots
+        );
+        let lsp_client = Self::start_lsp_client(config).await?;
+        info!("rust-analyzer LSP client initialized and ready");
+
+        let server = Self::with_client(config, lsp_client);
+        if let Some(timeout) = config.idle_timeout {
+            server.spawn_idle_watcher(timeout);
+        }
+        Ok(server)
+    }
+
+    /// A server driving an already initialized client.
+    fn with_client(config: &Config, lsp_client: LspClient) -> Self {
+        let mut tool_router = Self::tool_router();
+        if !config.enable_raw_lsp {
+            tool_router.remove_route("lsp_raw");
+        }
+
+        let listed_items = Arc::new(Mutex::new(ListedItems::default()));
+        Self {
+            lsp_client: Arc::new(Mutex::new(Some(Arc::new(lsp_client)))),
+            last_activity: Arc::new(Mutex::new(std::time::Instant::now())),
+            stopped_settings: Arc::new(Mutex::new(None)),
+            config: Arc::new(config.clone()),
+            workspace_roots: config.workspace_roots.clone(),
+            sessions: Arc::new(std::sync::Mutex::new(vec![Arc::downgrade(&listed_items)])),
+            listed_items,
+            tool_router,
+        }
+    }
+
+    /// A handle for another MCP session. It shares the language server but
+    /// numbers its own completion items and code actions, so one client's
+    /// results never replace the ones another is about to resolve or apply.
+    fn session(&self) -> Self {
+        let listed_items = Arc::new(Mutex::new(ListedItems::default()));
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|items| items.strong_count() > 0);
+        sessions.push(Arc::downgrade(&listed_items));
+        drop(sessions);
+
+        Self {
+            listed_items,
+            ..self.clone()
+        }
+    }
+
+    async fn start_lsp_client(config: &Config) -> Result<LspClient, LspError> {
+        LspClient::new(
+            &config.workspace_roots,
+            &config.server,
+            config.retry,
+            config.diagnostics_wait,
+            config.max_open_documents,
+            config.max_concurrent_requests,
+            config.allow_external_files,
+        )
+        .await
+    }
+
+    /// The language server client, starting the server again first if it was
+    /// shut down for being idle.
+    async fn client(&self) -> Result<Arc<LspClient>, McpError> {
+        let mut lsp_client = self.lsp_client.lock().await;
+        if lsp_client.is_none() {
+            info!("Starting the language server again after an idle shutdown");
+            // Keep settings changed with `configure` across the restart
+            let mut config = (*self.config).clone();
+            if let Some(settings) = self.stopped_settings.lock().await.clone() {
+                config.server.init_options = settings;
+            }
+            *lsp_client = Some(Arc::new(Self::start_lsp_client(&config).await?));
+        }
+        *self.last_activity.lock().await = std::time::Instant::now();
+        Ok(lsp_client
+            .clone()
+            .expect("language server was just started"))
+    }
+
+    /// Shut the language server down once no tool has used it for `timeout`.
+    fn spawn_idle_watcher(&self, timeout: std::time::Duration) {
+        let lsp_client = self.lsp_client.clone();
+        let last_activity = self.last_activity.clone();
+        let stopped_settings = self.stopped_settings.clone();
+        let sessions = self.sessions.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(timeout.min(IDLE_CHECK_INTERVAL));
+            loop {
+                interval.tick().await;
+                let mut client = lsp_client.lock().await;
+                // Another clone means a tool is using the server right now
+                if client
+                    .as_ref()
+                    .is_some_and(|client| Arc::strong_count(client) > 1)
+                {
+                    *last_activity.lock().await = std::time::Instant::now();
+                    continue;
+                }
+                if client.is_none() || last_activity.lock().await.elapsed() < timeout {
+                    continue;
+                }
+
+                info!(
+                    "No requests for {:?}, shutting the language server down until the next one",
+                    timeout
+                );
+                // No tool holds a clone and none can take one while locked
+                if let Some(client) = client.take().and_then(Arc::into_inner) {
+                    *stopped_settings.lock().await = Some(client.settings().await);
+                    // Listed items belong to the stopped server and can't be resolved
+                    // or applied by the next one
+                    let listed: Vec<_> = sessions
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter_map(Weak::upgrade)
+                        .collect();
+                    for items in listed {
+                        *items.lock().await = ListedItems::default();
+                    }
+                    if let Err(e) = client.shutdown().await {
+                        warn!("Failed to shut down the idle language server: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    #[tool(description = "Get type information and documentation at a specific position")]
+    async fn hover(
         &self,
-        Parameters(request): Parameters<GotoDefinitionRequest>,
+        Parameters(request): Parameters<HoverRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
         match lsp_client
-            .goto_definition(&request.file_path, request.line, request.column)
+            .hover(&request.file_path, request.line, request.column)
             .await
         {
-            Ok(Some(response)) => {
-                use lsp_types::GotoDefinitionResponse;
-                let locations = match response {
-                    GotoDefinitionResponse::Scalar(location) => vec![location],
-                    GotoDefinitionResponse::Array(locations) => locations,
-                    GotoDefinitionResponse::Link(links) => links
+            Ok(Some(hover)) if request.raw => {
+                let content = match hover.contents {
+                    lsp_types::HoverContents::Markup(markup) => markup.value,
+                    lsp_types::HoverContents::Scalar(lsp_types::MarkedString::String(s)) => s,
+                    // Marked strings have no single markdown form, so keep their structure
+                    contents => serde_json::to_string_pretty(&contents).map_err(LspError::from)?,
+                };
+                Ok(CallToolResult::success(vec![Content::text(content)]))
+            }
+            Ok(Some(hover)) if request.concise => {
+                let mut content = hover_signature(&hover.contents);
+                if let Some(range) = hover.range {
+                    content.push_str(&format_hover_range(range));
+                }
+                Ok(CallToolResult::success(vec![Content::text(content)]))
+            }
+            Ok(Some(hover)) => {
+                let format = request.format;
+                let mut content = match hover.contents {
+                    lsp_types::HoverContents::Markup(markup) => format.render_markup(markup),
+                    lsp_types::HoverContents::Array(markups) => markups
                         .into_iter()
-                        .map(|link| lsp_types::Location {
-                            uri: link.target_uri,
-                            range: link.target_selection_range,
+                        .map(|m| match m {
+                            lsp_types::MarkedString::String(s) => format.render(s),
+                            lsp_types::MarkedString::LanguageString(ls) => ls.value,
                         })
-                        .collect(),
+                        .collect::<Vec<_>>()
+                        .join("\n\n"),
+                    lsp_types::HoverContents::Scalar(ms) => match ms {
+                        lsp_types::MarkedString::String(s) => format.render(s),
+                        lsp_types::MarkedString::LanguageString(ls) => ls.value,
+                    },
                 };
+                if let Some(range) = hover.range {
+                    content.push_str(&format_hover_range(range));
+                }
+                Ok(CallToolResult::success(vec![Content::text(content)]))
+            }
+            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
+                "No hover information available",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Get code completions at a specific position")]
+    async fn completion(
+        &self,
+        Parameters(request): Parameters<CompletionRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client
+            .completion(
+                &request.file_path,
+                request.line,
+                request.column,
+                request.trigger_kind.map(Into::into),
+                request.trigger_character,
+            )
+            .await
+        {
+            Ok(Some(result)) => {
+                let mut completions = match result {
+                    lsp_types::CompletionResponse::Array(items) => items,
+                    lsp_types::CompletionResponse::List(list) => list.items,
+                };
+
+                if !request.raw_order {
+                    let prefix = if request.filter_prefix {
+                        lsp_client
+                            .read_file(&request.file_path)
+                            .await
+                            .ok()
+                            .and_then(|text| {
+                                let line = text.lines().nth(request.line as usize)?;
+                                Some(identifier_prefix(line, request.column))
+                            })
+                    } else {
+                        None
+                    };
+                    completions = rank_completions(completions, prefix.as_deref());
+                }
+
+                completions.truncate(10); // Limit to first 10 for readability
+                self.listed_items.lock().await.completions = completions.clone();
+
+                let completion_text = completions
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let kind = item
+                            .kind
+                            .map(|kind| format!("[{:?}] ", kind))
+                            .unwrap_or_default();
+                        let insert = match completion_insert_text(&item) {
+                            Some(text) if is_snippet(&item) => {
+                                let (expanded, placeholders) = expand_snippet(text);
+                                if placeholders.is_empty() {
+                                    format!(" (inserts `{}`)", expanded)
+                                } else {
+                                    format!(
+                                        " (snippet `{}`, inserts `{}`; placeholders {})",
+                                        text,
+                                        expanded,
+                                        format_placeholders(&placeholders)
+                                    )
+                                }
+                            }
+                            Some(text) if text != item.label => {
+                                format!(" (inserts `{}`)", text)
+                            }
+                            _ => String::new(),
+                        };
+                        let detail = item.detail.unwrap_or_default();
+                        let doc = item
+                            .documentation
+                            .map(|d| request.format.render_documentation(d))
+                            .unwrap_or_default();
+
+                        if doc.is_empty() {
+                            format!("{}. {}{}{}: {}", i + 1, kind, item.label, insert, detail)
+                        } else {
+                            format!(
+                                "{}. {}{}{}: {} - {}",
+                                i + 1,
+                                kind,
+                                item.label,
+                                insert,
+                                detail,
+                                doc
+                            )
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Completions:\n{}",
+                    completion_text
+                ))]))
+            }
+            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
+                "No completions available",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Get the full details of an item from the last completion result")]
+    async fn resolve_completion(
+        &self,
+        Parameters(request): Parameters<ResolveCompletionRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        let listed_items = self.listed_items.lock().await;
+        let item = request
+            .index
+            .checked_sub(1)
+            .and_then(|i| listed_items.completions.get(i).cloned())
+            .ok_or_else(|| {
+                McpError::invalid_params(
+                    format!(
+                        "No completion item {}; call completion first",
+                        request.index
+                    ),
+                    None,
+                )
+            })?;
+        let item = if lsp_client.supports_completion_resolve() {
+            lsp_client.resolve_completion_item(item).await?
+        } else {
+            item
+        };
+
+        let mut sections = vec![item.label.clone()];
+        if let Some(detail) = &item.detail {
+            sections.push(detail.clone());
+        }
+        if let Some(documentation) = &item.documentation {
+            sections.push(request.format.render_documentation(documentation.clone()));
+        }
+        match completion_insert_text(&item) {
+            Some(snippet) if is_snippet(&item) => {
+                let (expanded, placeholders) = expand_snippet(snippet);
+                sections.push(format!("Inserts:\n```rust\n{}\n```", expanded));
+                if !placeholders.is_empty() {
+                    sections.push(format!(
+                        "Snippet:\n```\n{}\n```\nPlaceholders: {}",
+                        snippet,
+                        format_placeholders(&placeholders)
+                    ));
+                }
+            }
+            Some(insert) => sections.push(format!("Inserts:\n```rust\n{}\n```", insert)),
+            None => {}
+        }
+        let additional = item.additional_text_edits.unwrap_or_default();
+        if !additional.is_empty() {
+            let edits = additional
+                .iter()
+                .map(|edit| {
+                    format!(
+                        "  {}: {}",
+                        display_range(edit.range),
+                        edit.new_text.trim_end().replace('\n', "\\n")
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            sections.push(format!("Additional edits:\n{}", edits));
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(
+            sections.join("\n\n"),
+        )]))
+    }
+
+    #[tool(description = "Get compile errors and warnings for a file")]
+    async fn diagnostics(
+        &self,
+        Parameters(request): Parameters<DiagnosticsRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client.diagnostics(&request.file_path).await {
+            Ok(diagnostics) => {
+                if diagnostics.is_empty() {
+                    Ok(CallToolResult::success(vec![Content::text(
+                        "No diagnostics found",
+                    )]))
+                } else {
+                    let diagnostic_text = diagnostics
+                        .into_iter()
+                        .map(format_diagnostic)
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    Ok(CallToolResult::success(vec![Content::text(format!(
+                        "Diagnostics:\n{}",
+                        diagnostic_text
+                    ))]))
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Find definition of symbol at position")]
+    async fn goto_definition(
+        &self,
+        Parameters(request): Parameters<GotoDefinitionRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client
+            .goto_definition(&request.file_path, request.line, request.column)
+            .await
+        {
+            Ok(Some(response)) => {
+                let locations = definition_locations(response);
 
                 if locations.is_empty() {
                     Ok(CallToolResult::success(vec![Content::text(
@@ -332,8 +1827,9 @@ impl RustAnalyzerMCP {
                                 .and_then(|p| p.to_str().map(|s| s.to_string()))
                                 .unwrap_or_else(|| loc.uri.to_string());
                             format!(
-                                "Definition at: {}:{}:{}",
-                                path, loc.range.start.line, loc.range.start.character
+                                "Definition at: {}:{}",
+                                path,
+                                display_position(loc.range.start)
                             )
                         })
                         .collect::<Vec<_>>()
@@ -348,60 +1844,373 @@ impl RustAnalyzerMCP {
             Ok(None) => Ok(CallToolResult::success(vec![Content::text(
                 "No definition found",
             )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Get the full source of the item a symbol is defined by")]
+    async fn definition_source(
+        &self,
+        Parameters(request): Parameters<DefinitionSourceRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        let locations = lsp_client
+            .goto_definition(&request.file_path, request.line, request.column)
+            .await?
+            .map(definition_locations)
+            .unwrap_or_default();
+        if locations.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No definition found",
+            )]));
+        }
+
+        let mut sections = Vec::new();
+        for location in locations {
+            let path = location
+                .uri
+                .to_file_path()
+                .ok()
+                .and_then(|p| p.to_str().map(|s| s.to_string()))
+                .unwrap_or_else(|| location.uri.to_string());
+            let source = lsp_client.read_file(&path).await?;
+            let position = location.range.start;
+
+            let item_range = match lsp_client.document_symbols(&path).await? {
+                Some(response) => enclosing_symbol_range(response, position),
+                None => None,
+            };
+            let (range, note) = match item_range {
+                Some(range) => (range, ""),
+                None => {
+                    // No enclosing item, so show the lines around the definition
+                    let start = position.line.saturating_sub(DEFINITION_CONTEXT_LINES);
+                    let end = position.line + DEFINITION_CONTEXT_LINES + 1;
+                    // Stop at the end of the file for definitions near it
+                    let end = lsp_types::Position::new(end, 0)
+                        .min(position::position_at(&source, source.len()));
+                    let range = lsp_types::Range {
+                        start: lsp_types::Position::new(start, 0),
+                        end,
+                    };
+                    (range, " (not inside an item, showing nearby lines)")
+                }
+            };
+
+            let text = range_text(&source, range).unwrap_or_default();
+            sections.push(format!(
+                "{}:{}{}\n```rust\n{}\n```",
+                path,
+                display_range(range),
+                note,
+                text.trim_end()
+            ));
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(
+            sections.join("\n\n"),
+        )]))
+    }
+
+    #[tool(description = "Find all references to symbol at position")]
+    async fn find_references(
+        &self,
+        Parameters(request): Parameters<FindReferencesRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client
+            .find_references(
+                &request.file_path,
+                request.line,
+                request.column,
+                request.include_declaration,
+            )
+            .await
+        {
+            Ok(Some(locations)) if !locations.is_empty() => {
+                let total = locations.len();
+                let mut by_file: BTreeMap<String, Vec<lsp_types::Range>> = BTreeMap::new();
+                for loc in locations {
+                    let path = loc
+                        .uri
+                        .to_file_path()
+                        .ok()
+                        .and_then(|p| p.to_str().map(|s| s.to_string()))
+                        .unwrap_or_else(|| loc.uri.to_string());
+                    by_file.entry(path).or_default().push(loc.range);
+                }
+
+                let mut sections = Vec::new();
+                for (path, mut ranges) in by_file {
+                    ranges.sort_by_key(|range| (range.start.line, range.start.character));
+                    // Read each file once for the previews, skipping ones that can't be read
+                    let source = if request.preview {
+                        lsp_client.read_file(&path).await.ok()
+                    } else {
+                        None
+                    };
+
+                    let mut section = format!("{} ({})", path, ranges.len());
+                    for range in ranges {
+                        section.push_str(&format!("\n  {}", display_position(range.start)));
+                        let line = source
+                            .as_deref()
+                            .and_then(|text| text.lines().nth(range.start.line as usize));
+                        if let Some(line) = line {
+                            section.push_str(&format!("  {}", line.trim()));
+                        }
+                    }
+                    sections.push(section);
+                }
+
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Found {} references in {} files:\n\n{}",
+                    total,
+                    sections.len(),
+                    sections.join("\n\n")
+                ))]))
+            }
+            Ok(_) => Ok(CallToolResult::success(vec![Content::text(
+                "No references found",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Count references to a symbol, broken down per file")]
+    async fn usage_summary(
+        &self,
+        Parameters(request): Parameters<UsageSummaryRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client
+            .find_references(
+                &request.file_path,
+                request.line,
+                request.column,
+                request.include_declaration,
+            )
+            .await
+        {
+            Ok(Some(locations)) if !locations.is_empty() => {
+                let total = locations.len();
+                let mut by_file: BTreeMap<String, Vec<lsp_types::Range>> = BTreeMap::new();
+                for loc in locations {
+                    let path = loc
+                        .uri
+                        .to_file_path()
+                        .ok()
+                        .and_then(|p| p.to_str().map(|s| s.to_string()))
+                        .unwrap_or_else(|| loc.uri.to_string());
+                    by_file.entry(path).or_default().push(loc.range);
+                }
+
+                // Most-used files first, ties in path order
+                let mut files = by_file.into_iter().collect::<Vec<_>>();
+                files.sort_by_key(|(_, ranges)| std::cmp::Reverse(ranges.len()));
+
+                let file_count = files.len();
+                let breakdown = files
+                    .into_iter()
+                    .map(|(path, mut ranges)| {
+                        let mut entry = format!("  {}: {}", path, ranges.len());
+                        if request.include_locations {
+                            ranges.sort_by_key(|range| (range.start.line, range.start.character));
+                            for range in ranges {
+                                entry.push_str(&format!("\n    {}", display_position(range.start)));
+                            }
+                        }
+                        entry
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "{} references in {} files:\n{}",
+                    total, file_count, breakdown
+                ))]))
+            }
+            Ok(_) => Ok(CallToolResult::success(vec![Content::text(
+                "No references found",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Explain a symbol: its type and docs, definition and usage count")]
+    async fn explain_symbol(
+        &self,
+        Parameters(request): Parameters<ExplainSymbolRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        let (hover, definition, references) = tokio::join!(
+            lsp_client.hover(&request.file_path, request.line, request.column),
+            lsp_client.goto_definition(&request.file_path, request.line, request.column),
+            lsp_client.find_references(&request.file_path, request.line, request.column, false),
+        );
+
+        let mut sections = Vec::new();
+
+        let summary = match hover? {
+            Some(hover) => hover_markdown(&hover.contents),
+            None => "No hover information available".to_string(),
+        };
+        sections.push(format!("## Type and documentation\n{}", summary.trim_end()));
+
+        let locations = definition?.map(definition_locations).unwrap_or_default();
+        let definitions = if locations.is_empty() {
+            "No definition found".to_string()
+        } else {
+            locations
+                .into_iter()
+                .map(|loc| {
+                    let path = loc
+                        .uri
+                        .to_file_path()
+                        .ok()
+                        .and_then(|p| p.to_str().map(|s| s.to_string()))
+                        .unwrap_or_else(|| loc.uri.to_string());
+                    format!("{}:{}", path, display_position(loc.range.start))
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        sections.push(format!("## Defined at\n{}", definitions));
+
+        let references = references?.unwrap_or_default();
+        let mut files = references.iter().map(|loc| &loc.uri).collect::<Vec<_>>();
+        files.sort();
+        files.dedup();
+        sections.push(format!(
+            "## Usage\n{} references in {} files",
+            references.len(),
+            files.len()
+        ));
+
+        Ok(CallToolResult::success(vec![Content::text(
+            sections.join("\n\n"),
+        )]))
+    }
+
+    #[tool(description = "Get the crate/module path of a symbol, for use statements")]
+    async fn symbol_path(
+        &self,
+        Parameters(request): Parameters<SymbolPathRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        let source = lsp_client.read_file(&request.file_path).await?;
+        let name = source
+            .lines()
+            .nth(request.line as usize)
+            .map(|line| identifier_at(line, request.column))
+            .unwrap_or_default();
+        if name.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No symbol at this position",
+            )]));
+        }
+
+        let blocks = lsp_client
+            .hover(&request.file_path, request.line, request.column)
+            .await?
+            .map(|hover| markdown::header_code_blocks(&hover_markdown(&hover.contents)))
+            .unwrap_or_default();
+        if blocks.last().is_some_and(|sig| sig.starts_with("let ")) {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "`{}` is a local binding and has no path",
+                name
+            ))]));
+        }
+
+        let definition = lsp_client
+            .goto_definition(&request.file_path, request.line, request.column)
+            .await?
+            .and_then(|response| definition_locations(response).into_iter().next());
+        let definition_file = definition
+            .as_ref()
+            .and_then(|location| location.uri.to_file_path().ok());
+        let request_file = PathBuf::from(lsp_client.resolve_path(&request.file_path));
+        let same_package = definition_file
+            .as_deref()
+            .and_then(package_root)
+            .is_some_and(|root| package_root(&request_file) == Some(root));
+
+        // rust-analyzer's hover leads with the containing module's path,
+        // starting from the crate name
+        if blocks.len() >= 2 {
+            let full_path = format!("{}::{}", blocks[0], name);
+            if !same_package {
+                return Ok(CallToolResult::success(vec![Content::text(full_path)]));
+            }
+            let local_path = match full_path.split_once("::") {
+                Some((_, rest)) => format!("crate::{}", rest),
+                None => full_path.clone(),
+            };
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "{}\n(from other crates: {})",
+                local_path, full_path
+            ))]));
         }
+
+        // No path in the hover: infer it from where the definition lives
+        let (Some(definition), Some(definition_file)) = (definition, definition_file) else {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "Could not determine a path for `{}`",
+                name
+            ))]));
+        };
+        let Some(module_path) = module_path_from_file(&definition_file) else {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "Could not determine a path for `{}`: its definition in {} is outside a package's src directory",
+                name,
+                definition_file.display()
+            ))]));
+        };
+        let definition_path = definition_file.to_string_lossy();
+        let segments = match lsp_client.document_symbols(&definition_path).await? {
+            Some(lsp_types::DocumentSymbolResponse::Nested(symbols)) => {
+                enclosing_path_segments(&symbols, definition.range.start)
+            }
+            _ => Vec::new(),
+        };
+
+        let mut path = vec![module_path];
+        path.extend(segments);
+        path.push(name);
+        let caveat = if same_package {
+            ""
+        } else {
+            "; `crate` refers to the defining package"
+        };
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "{}\n(inferred from the definition's file layout{})",
+            path.join("::"),
+            caveat
+        ))]))
     }
 
-    #[tool(description = "Find all references to symbol at position")]
-    async fn find_references(
+    #[tool(description = "Apply explicit text edits to a file and write it")]
+    async fn apply_edits(
         &self,
-        Parameters(request): Parameters<FindReferencesRequest>,
+        Parameters(request): Parameters<ApplyEditsRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
-        match lsp_client
-            .find_references(
-                &request.file_path,
-                request.line,
-                request.column,
-                request.include_declaration,
-            )
-            .await
-        {
-            Ok(Some(locations)) => {
-                if locations.is_empty() {
-                    Ok(CallToolResult::success(vec![Content::text(
-                        "No references found",
-                    )]))
-                } else {
-                    let references_text = locations
-                        .into_iter()
-                        .map(|loc| {
-                            let path = loc
-                                .uri
-                                .to_file_path()
-                                .ok()
-                                .and_then(|p| p.to_str().map(|s| s.to_string()))
-                                .unwrap_or_else(|| loc.uri.to_string());
-                            format!(
-                                "Reference at: {}:{}:{}",
-                                path, loc.range.start.line, loc.range.start.character
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n");
+        let edit_count = request.edits.len();
+        let text = lsp_client.read_file(&request.file_path).await?;
+        let new_text = apply_text_edits(&text, request.edits)
+            .map_err(|e| McpError::invalid_params(format!("Cannot apply edits: {}", e), None))?;
+        lsp_client.write_file(&request.file_path, &new_text).await?;
 
-                    Ok(CallToolResult::success(vec![Content::text(format!(
-                        "Found references:\n{}",
-                        references_text
-                    ))]))
-                }
-            }
-            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
-                "No references found",
-            )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
-        }
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Applied {} edits to {}:\n\n{}",
+            edit_count, request.file_path, new_text
+        ))]))
     }
 
     #[tool(description = "Format Rust code")]
@@ -409,10 +2218,11 @@ impl RustAnalyzerMCP {
         &self,
         Parameters(request): Parameters<FormatRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
-        let result = lsp_client.format_document(&request.file_path).await;
-        drop(lsp_client); // Release the lock before doing async I/O
+        let result = lsp_client
+            .format_document(&request.file_path, request.tab_size, request.insert_spaces)
+            .await;
 
         match result {
             Ok(Some(edits)) => {
@@ -433,7 +2243,72 @@ impl RustAnalyzerMCP {
             Ok(None) => Ok(CallToolResult::success(vec![Content::text(
                 "No formatting changes needed",
             )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Format only a selected range of Rust code")]
+    async fn format_range(
+        &self,
+        Parameters(request): Parameters<FormatRangeRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        let range = lsp_types::Range {
+            start: lsp_types::Position {
+                line: request.start_line,
+                character: request.start_column,
+            },
+            end: lsp_types::Position {
+                line: request.end_line,
+                character: request.end_column,
+            },
+        };
+
+        // Fall back to whole-file formatting when the server doesn't offer range formatting
+        let (result, note) = if lsp_client.supports_range_formatting() {
+            (
+                lsp_client
+                    .format_range(
+                        &request.file_path,
+                        range,
+                        request.tab_size,
+                        request.insert_spaces,
+                    )
+                    .await,
+                None,
+            )
+        } else {
+            (
+                lsp_client
+                    .format_document(&request.file_path, request.tab_size, request.insert_spaces)
+                    .await,
+                Some("Note: server does not support range formatting, formatted the whole file instead"),
+            )
+        };
+
+        match result {
+            Ok(Some(edits)) if !edits.is_empty() => {
+                let mut lines = Vec::new();
+                if let Some(note) = note {
+                    lines.push(note.to_string());
+                }
+                lines.push(format!("Formatting would apply {} edits:", edits.len()));
+                for edit in &edits {
+                    lines.push(format!(
+                        "  - {}: Replace with '{}'",
+                        display_range(edit.range),
+                        edit.new_text.trim_end_matches('\n').replace('\n', "\\n")
+                    ));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    lines.join("\n"),
+                )]))
+            }
+            Ok(_) => Ok(CallToolResult::success(vec![Content::text(
+                "No formatting changes needed",
+            )])),
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -442,7 +2317,7 @@ impl RustAnalyzerMCP {
         &self,
         Parameters(request): Parameters<RenameRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
         match lsp_client
             .rename(
@@ -453,6 +2328,52 @@ impl RustAnalyzerMCP {
             )
             .await
         {
+            Ok(Some(workspace_edit)) if request.diff => {
+                let by_file = text_edits_by_file(workspace_edit);
+                let edit_count: usize = by_file.values().map(Vec::len).sum();
+
+                let mut diffs = Vec::new();
+                for (path, edits) in &by_file {
+                    let old = lsp_client.read_file(path).await?;
+                    let infos = edits.iter().map(text_edit_info).collect();
+                    let new = apply_text_edits(&old, infos).map_err(|e| {
+                        McpError::internal_error(
+                            format!("Cannot apply rename edits to {}: {}", path, e),
+                            None,
+                        )
+                    })?;
+                    let name =
+                        resources::display_name(&self.workspace_roots, std::path::Path::new(path));
+                    let diff = diff::unified(&name, &old, &new);
+                    let lines = diff.lines().collect::<Vec<_>>();
+                    let mut shown = lines
+                        .iter()
+                        .take(RENAME_DIFF_MAX_LINES)
+                        .copied()
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    if lines.len() > RENAME_DIFF_MAX_LINES {
+                        shown.push_str(&format!(
+                            "\n… ({} more lines)",
+                            lines.len() - RENAME_DIFF_MAX_LINES
+                        ));
+                    }
+                    diffs.push(format!("```diff\n{}\n```", shown));
+                }
+
+                if diffs.is_empty() {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        "No changes needed for rename",
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Renaming to '{}' changes {} files ({} edits):\n\n{}",
+                    request.new_name,
+                    by_file.len(),
+                    edit_count,
+                    diffs.join("\n\n")
+                ))]))
+            }
             Ok(Some(workspace_edit)) => {
                 let mut changes_description = Vec::new();
 
@@ -463,11 +2384,9 @@ impl RustAnalyzerMCP {
 
                         for edit in &edits {
                             changes_description.push(format!(
-                                "  - Line {}-{}: Replace '{}' with '{}'",
-                                edit.range.start.line + 1,
-                                edit.range.end.line + 1,
-                                edit.new_text.trim_end_matches('\n').replace('\n', "\\n"),
-                                request.new_name
+                                "  - {}: Replace with '{}'",
+                                display_range(edit.range),
+                                edit.new_text.trim_end_matches('\n').replace('\n', "\\n")
                             ));
                         }
                     }
@@ -498,9 +2417,8 @@ impl RustAnalyzerMCP {
                                         OneOf::Right(annotated) => &annotated.text_edit,
                                     };
                                     changes_description.push(format!(
-                                        "  - Line {}-{}: Replace with '{}'",
-                                        text_edit.range.start.line + 1,
-                                        text_edit.range.end.line + 1,
+                                        "  - {}: Replace with '{}'",
+                                        display_range(text_edit.range),
                                         text_edit
                                             .new_text
                                             .trim_end_matches('\n')
@@ -532,8 +2450,90 @@ impl RustAnalyzerMCP {
             Ok(None) => Ok(CallToolResult::success(vec![Content::text(
                 "Cannot rename at this position",
             )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Check that a symbol can be renamed and preview the rename in one call")]
+    async fn prepare_and_rename(
+        &self,
+        Parameters(request): Parameters<PrepareAndRenameRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+        let not_renameable = |reason: &str| {
+            Ok(CallToolResult::success(vec![Content::text(format!(
+                "renameable: false\nreason: {}",
+                reason
+            ))]))
+        };
+
+        let mut current_name = None;
+        if lsp_client.supports_prepare_rename() {
+            use lsp_types::PrepareRenameResponse;
+            let range = match lsp_client
+                .prepare_rename(&request.file_path, request.line, request.column)
+                .await
+            {
+                Ok(Some(PrepareRenameResponse::Range(range))) => Some(range),
+                Ok(Some(PrepareRenameResponse::RangeWithPlaceholder { range, placeholder })) => {
+                    current_name = Some(placeholder);
+                    Some(range)
+                }
+                Ok(Some(PrepareRenameResponse::DefaultBehavior { .. })) => None,
+                Ok(None) => return not_renameable("No renameable symbol at this position"),
+                // rust-analyzer explains why a rename isn't possible in the error message
+                Err(LspError::Protocol { error, .. }) => return not_renameable(&error.message),
+                Err(e) => return Err(e.into()),
+            };
+
+            if current_name.is_none() {
+                if let Some(range) = range {
+                    let text = lsp_client.read_file(&request.file_path).await?;
+                    current_name = range_text(&text, range);
+                }
+            }
         }
+
+        let workspace_edit = match lsp_client
+            .rename(
+                &request.file_path,
+                request.line,
+                request.column,
+                &request.new_name,
+            )
+            .await
+        {
+            Ok(Some(workspace_edit)) => workspace_edit,
+            Ok(None) => return not_renameable("No renameable symbol at this position"),
+            Err(LspError::Protocol { error, .. }) => return not_renameable(&error.message),
+            Err(e) => return Err(e.into()),
+        };
+
+        let by_file = text_edits_by_file(workspace_edit);
+        let total: usize = by_file.values().map(Vec::len).sum();
+        let mut lines = vec![
+            "renameable: true".to_string(),
+            format!(
+                "current_name: {}",
+                current_name.as_deref().unwrap_or("(unknown)")
+            ),
+            format!("edits: {} in {} files", total, by_file.len()),
+        ];
+        for (path, edits) in by_file {
+            lines.push(String::new());
+            lines.push(path);
+            for edit in edits {
+                lines.push(format!(
+                    "  {} → '{}'",
+                    display_range(edit.range),
+                    edit.new_text.replace('\n', "\\n")
+                ));
+            }
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(
+            lines.join("\n"),
+        )]))
     }
 
     #[tool(description = "Get available quick fixes and refactorings")]
@@ -541,16 +2541,18 @@ impl RustAnalyzerMCP {
         &self,
         Parameters(request): Parameters<CodeActionsRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
+        let only = request.only.map(parse_code_action_kinds).transpose()?;
         match lsp_client
-            .code_actions(&request.file_path, request.line, request.column)
+            .code_actions(&request.file_path, request.line, request.column, only)
             .await
         {
             Ok(Some(actions)) => {
                 let mut action_descriptions = Vec::new();
+                self.listed_items.lock().await.code_actions = actions.clone();
 
-                for action in actions {
+                for (i, action) in actions.into_iter().enumerate() {
                     use lsp_types::CodeActionOrCommand;
                     match action {
                         CodeActionOrCommand::CodeAction(code_action) => {
@@ -572,8 +2574,13 @@ impl RustAnalyzerMCP {
                                 String::new()
                             };
 
-                            action_descriptions
-                                .push(format!("• {}{}{}", title, kind, diagnostics_info));
+                            action_descriptions.push(format!(
+                                "{}. {}{}{}",
+                                i + 1,
+                                title,
+                                kind,
+                                diagnostics_info
+                            ));
 
                             // If there's a workspace edit, show what it would change
                             if let Some(edit) = &code_action.edit {
@@ -609,8 +2616,10 @@ impl RustAnalyzerMCP {
                         }
                         CodeActionOrCommand::Command(command) => {
                             action_descriptions.push(format!(
-                                "• {} (command: {})",
-                                command.title, command.command
+                                "{}. {} (command: {})",
+                                i + 1,
+                                command.title,
+                                command.command
                             ));
                         }
                     }
@@ -631,8 +2640,243 @@ impl RustAnalyzerMCP {
             Ok(None) => Ok(CallToolResult::success(vec![Content::text(
                 "No code actions available at this position",
             )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Apply an action from the last code_actions result")]
+    async fn apply_code_action(
+        &self,
+        Parameters(request): Parameters<ApplyCodeActionRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        let listed_items = self.listed_items.lock().await;
+        let action = request
+            .index
+            .checked_sub(1)
+            .and_then(|i| listed_items.code_actions.get(i).cloned())
+            .ok_or_else(|| {
+                McpError::invalid_params(
+                    format!("No code action {}; call code_actions first", request.index),
+                    None,
+                )
+            })?;
+        let action = match action {
+            lsp_types::CodeActionOrCommand::CodeAction(action) => action,
+            lsp_types::CodeActionOrCommand::Command(command) => {
+                return Ok(CallToolResult::success(vec![Content::text(format!(
+                    "'{}' is a command ({}) with no edit to apply",
+                    command.title, command.command
+                ))]));
+            }
+        };
+
+        // Edits may be computed lazily, resolve them when the server allows it
+        let needs_resolve = action.edit.is_none();
+        let action = if needs_resolve && lsp_client.supports_code_action_resolve() {
+            lsp_client.resolve_code_action(action).await?
+        } else {
+            action
+        };
+
+        let Some(edit) = action.edit else {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "'{}' has no edit to apply",
+                action.title
+            ))]));
+        };
+        let files = apply_workspace_edit(&lsp_client, edit).await?;
+
+        let mut text = format!(
+            "Applied '{}' to {} files:\n{}",
+            action.title,
+            files.len(),
+            files
+                .iter()
+                .map(|path| format!("  {}", path))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        if let Some(command) = action.command {
+            text.push_str(&format!(
+                "\n\nThe follow-up command {} was not run",
+                command.command
+            ));
+        }
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(description = "Apply the quick fixes for every diagnostic in a file")]
+    async fn fix_all(
+        &self,
+        Parameters(request): Parameters<FixAllRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        use lsp_types::{CodeActionKind, CodeActionOrCommand};
+
+        let lsp_client = self.client().await?;
+
+        let diagnostics = lsp_client.diagnostics(&request.file_path).await?;
+        let source = lsp_client.read_file(&request.file_path).await?;
+
+        // The chosen fixes, labelled for the report
+        let mut fixes = Vec::new();
+        let mut not_applied = Vec::new();
+
+        let mut fix_all = None;
+        if request.use_fix_all {
+            let whole_file = lsp_types::Range {
+                start: lsp_types::Position::default(),
+                end: lsp_types::Position {
+                    line: source.lines().count() as u32,
+                    character: 0,
+                },
+            };
+            fix_all = lsp_client
+                .code_actions_in_range(
+                    &request.file_path,
+                    whole_file,
+                    diagnostics.clone(),
+                    Some(vec![CodeActionKind::SOURCE_FIX_ALL]),
+                )
+                .await?
+                .unwrap_or_default()
+                .into_iter()
+                .find_map(|action| match action {
+                    CodeActionOrCommand::CodeAction(action) => {
+                        action.edit.clone().map(|edit| (action.title, edit))
+                    }
+                    CodeActionOrCommand::Command(_) => None,
+                });
+        }
+
+        if let Some((title, edit)) = fix_all {
+            fixes.push((format!("{} (source.fixAll)", title), edit));
+        } else {
+            for diagnostic in diagnostics {
+                let label = format!(
+                    "{} {}",
+                    display_position(diagnostic.range.start),
+                    diagnostic.message.lines().next().unwrap_or_default()
+                );
+                let mut actions = lsp_client
+                    .code_actions_in_range(
+                        &request.file_path,
+                        diagnostic.range,
+                        vec![diagnostic.clone()],
+                        Some(vec![CodeActionKind::QUICKFIX]),
+                    )
+                    .await?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|action| match action {
+                        CodeActionOrCommand::CodeAction(action) => Some(action),
+                        CodeActionOrCommand::Command(_) => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                // Only apply a fix the server prefers or one without alternatives
+                let action = match actions.iter().position(|a| a.is_preferred == Some(true)) {
+                    Some(index) => actions.swap_remove(index),
+                    None if actions.len() == 1 => actions.remove(0),
+                    None if actions.is_empty() => {
+                        not_applied.push(format!("{}: no quick fix available", label));
+                        continue;
+                    }
+                    None => {
+                        let titles = actions
+                            .iter()
+                            .map(|a| a.title.as_str())
+                            .collect::<Vec<_>>()
+                            .join("; ");
+                        not_applied.push(format!(
+                            "{}: {} possible fixes, pick one with code_actions ({})",
+                            label,
+                            actions.len(),
+                            titles
+                        ));
+                        continue;
+                    }
+                };
+                match action.edit {
+                    Some(edit) => fixes.push((format!("{} → {}", label, action.title), edit)),
+                    None => not_applied.push(format!(
+                        "{}: '{}' has no edit to apply",
+                        label, action.title
+                    )),
+                }
+            }
+        }
+
+        // Fixes are computed against the current text, so one that overlaps an
+        // earlier fix can't be combined with it
+        let mut texts: BTreeMap<String, String> = BTreeMap::new();
+        let mut accepted: BTreeMap<String, Vec<lsp_types::TextEdit>> = BTreeMap::new();
+        let mut applied = Vec::new();
+        'fixes: for (label, edit) in fixes {
+            let by_file = text_edits_by_file(edit);
+            let mut combined = Vec::new();
+            for (path, edits) in by_file {
+                if !texts.contains_key(&path) {
+                    texts.insert(path.clone(), lsp_client.read_file(&path).await?);
+                }
+                let mut file_edits = accepted.get(&path).cloned().unwrap_or_default();
+                file_edits.extend(edits);
+                let infos = file_edits.iter().map(text_edit_info).collect();
+                if let Err(e) = apply_text_edits(&texts[&path], infos) {
+                    not_applied.push(format!("{}: {}", label, e));
+                    continue 'fixes;
+                }
+                combined.push((path, file_edits));
+            }
+            accepted.extend(combined);
+            applied.push(label);
+        }
+
+        if !request.dry_run {
+            for (path, edits) in &accepted {
+                let infos = edits.iter().map(text_edit_info).collect();
+                let new_text = apply_text_edits(&texts[path], infos)
+                    .map_err(|e| McpError::internal_error(e, None))?;
+                lsp_client.write_file(path, &new_text).await?;
+            }
+        }
+
+        let mut sections = Vec::new();
+        if applied.is_empty() {
+            sections.push("No fixes to apply".to_string());
+        } else {
+            let verb = if request.dry_run {
+                "Would apply"
+            } else {
+                "Applied"
+            };
+            sections.push(format!(
+                "{} {} fixes in {} files:\n{}",
+                verb,
+                applied.len(),
+                accepted.len(),
+                applied
+                    .iter()
+                    .map(|label| format!("• {}", label))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ));
+        }
+        if !not_applied.is_empty() {
+            sections.push(format!(
+                "Not applied:\n{}",
+                not_applied
+                    .iter()
+                    .map(|reason| format!("• {}", reason))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ));
         }
+
+        Ok(CallToolResult::success(vec![Content::text(
+            sections.join("\n\n"),
+        )]))
     }
 
     #[tool(description = "Search for symbols across entire workspace")]
@@ -640,56 +2884,90 @@ impl RustAnalyzerMCP {
         &self,
         Parameters(request): Parameters<WorkspaceSymbolsRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
-        match lsp_client.workspace_symbols(&request.query).await {
-            Ok(Some(symbols)) => {
-                if symbols.is_empty() {
-                    Ok(CallToolResult::success(vec![Content::text(
-                        "No symbols found matching the query",
-                    )]))
-                } else {
-                    let symbol_text = symbols
-                        .into_iter()
-                        .take(20) // Limit to first 20 for readability
-                        .map(|symbol| {
-                            let location = symbol.location;
-                            let file_path = location
-                                .uri
-                                .to_file_path()
-                                .ok()
-                                .and_then(|p| p.to_str().map(|s| s.to_string()))
-                                .unwrap_or_else(|| location.uri.to_string());
-                            let kind = format!("{:?}", symbol.kind);
-                            let container = symbol
-                                .container_name
-                                .map(|c| format!(" (in {})", c))
-                                .unwrap_or_default();
+        let timeout = request.timeout_ms.map(std::time::Duration::from_millis);
+        let (symbols, complete) = lsp_client
+            .workspace_symbols(&request.query, timeout)
+            .await?;
+        if symbols.is_empty() {
+            let message = if complete {
+                "No symbols found matching the query"
+            } else {
+                "No symbols found before the search timed out"
+            };
+            return Ok(CallToolResult::success(vec![Content::text(message)]));
+        }
 
-                            format!(
-                                "• {} [{}]: {}:{}:{}{}",
-                                symbol.name,
-                                kind,
-                                file_path,
-                                location.range.start.line + 1,
-                                location.range.start.character + 1,
-                                container
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n");
+        let matches = symbols
+            .into_iter()
+            .filter(|symbol| symbol_kind_matches(&request.kinds, symbol.kind))
+            .map(|symbol| {
+                let file_path = symbol
+                    .location
+                    .uri
+                    .to_file_path()
+                    .ok()
+                    .and_then(|p| p.to_str().map(|s| s.to_string()))
+                    .unwrap_or_else(|| symbol.location.uri.to_string());
+                (symbol, file_path)
+            })
+            .filter(|(_, file_path)| {
+                request
+                    .path_contains
+                    .as_ref()
+                    .is_none_or(|needle| file_path.contains(needle.as_str()))
+            })
+            .collect::<Vec<_>>();
 
-                    Ok(CallToolResult::success(vec![Content::text(format!(
-                        "Found symbols:\n{}",
-                        symbol_text
-                    ))]))
-                }
-            }
-            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
-                "No symbols found matching the query",
-            )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+        if matches.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No symbols found matching the query and filters",
+            )]));
+        }
+
+        let total = matches.len();
+        let limit = request.limit.unwrap_or(20);
+        let symbol_text = matches
+            .into_iter()
+            .take(limit)
+            .map(|(symbol, file_path)| {
+                let location = symbol.location;
+                let kind = format!("{:?}", symbol.kind);
+                let container = symbol
+                    .container_name
+                    .map(|c| format!(" (in {})", c))
+                    .unwrap_or_default();
+
+                format!(
+                    "• {} [{}]: {}:{}{}",
+                    symbol.name,
+                    kind,
+                    file_path,
+                    display_position(location.range.start),
+                    container
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut notes = Vec::new();
+        if total > limit {
+            notes.push(format!("showing {} of {} matches", limit, total));
+        }
+        if !complete {
+            notes.push("the search timed out, so more may exist".to_string());
         }
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!("\n\n({})", notes.join("; "))
+        };
+
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Found symbols:\n{}{}",
+            symbol_text, notes
+        ))]))
     }
 
     #[tool(description = "Get type and parameter hints")]
@@ -697,18 +2975,39 @@ impl RustAnalyzerMCP {
         &self,
         Parameters(request): Parameters<InlayHintsRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        if let (Some(start), Some(end)) = (request.start_line, request.end_line) {
+            if start > end {
+                return Err(McpError::invalid_params(
+                    format!("start_line {} is after end_line {}", start, end),
+                    None,
+                ));
+            }
+        }
+        let lsp_client = self.client().await?;
 
-        match lsp_client.inlay_hints(&request.file_path).await {
+        match lsp_client
+            .inlay_hints(&request.file_path, request.start_line, request.end_line)
+            .await
+        {
             Ok(Some(hints)) => {
                 if hints.is_empty() {
                     Ok(CallToolResult::success(vec![Content::text(
                         "No inlay hints available",
                     )]))
                 } else {
-                    let hints_text = hints
+                    // Limit to first 50 for readability, resolving deferred tooltips
+                    let mut resolved_hints = Vec::new();
+                    for hint in hints.into_iter().take(50) {
+                        let needs_resolve = hint.data.is_some() && hint.tooltip.is_none();
+                        if needs_resolve && lsp_client.supports_inlay_hint_resolve() {
+                            resolved_hints.push(lsp_client.resolve_inlay_hint(hint).await?);
+                        } else {
+                            resolved_hints.push(hint);
+                        }
+                    }
+
+                    let hints_text = resolved_hints
                         .into_iter()
-                        .take(50) // Limit to first 50 for readability
                         .map(|hint| {
                             let position = hint.position;
                             let label = match hint.label {
@@ -720,13 +3019,22 @@ impl RustAnalyzerMCP {
                                     .join(""),
                             };
                             let kind = hint.kind.map(|k| format!(" ({:?})", k)).unwrap_or_default();
+                            let tooltip = hint
+                                .tooltip
+                                .map(|t| match t {
+                                    lsp_types::InlayHintTooltip::String(s) => s,
+                                    lsp_types::InlayHintTooltip::MarkupContent(mc) => mc.value,
+                                })
+                                .filter(|t| !t.is_empty())
+                                .map(|t| format!(" - {}", t))
+                                .unwrap_or_default();
 
                             format!(
-                                "Line {}:{}: {}{}",
-                                position.line + 1,
-                                position.character + 1,
+                                "Line {}: {}{}{}",
+                                display_position(position),
                                 label,
-                                kind
+                                kind,
+                                tooltip
                             )
                         })
                         .collect::<Vec<_>>()
@@ -741,7 +3049,7 @@ impl RustAnalyzerMCP {
             Ok(None) => Ok(CallToolResult::success(vec![Content::text(
                 "No inlay hints available",
             )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -750,47 +3058,112 @@ impl RustAnalyzerMCP {
         &self,
         Parameters(request): Parameters<ExpandMacroRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
-        match lsp_client
+        if !lsp_client.is_rust_analyzer() {
+            return Ok(unsupported_by_backend("expand_macro"));
+        }
+
+        let expansion = lsp_client
             .expand_macro(&request.file_path, request.line, request.column)
-            .await
-        {
-            Ok(Some(expansion)) => {
-                // The result structure depends on rust-analyzer's specific response format
-                // It typically contains expanded code as a string
-                let expansion_text = if let Some(expansion_str) = expansion.as_str() {
-                    expansion_str.to_string()
-                } else if let Some(obj) = expansion.as_object() {
-                    // Try to extract the expanded text from the response object
-                    if let Some(expanded) = obj.get("expansion").and_then(|v| v.as_str()) {
-                        expanded.to_string()
-                    } else {
-                        format!(
-                            "Macro expansion result: {}",
-                            serde_json::to_string_pretty(&expansion).unwrap_or_default()
-                        )
-                    }
-                } else {
-                    format!("Macro expansion result: {}", expansion)
+            .await?;
+        let Some(expansion) = expansion else {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No macro expansion available at this position",
+            )]));
+        };
+
+        // The result structure depends on rust-analyzer's specific response format
+        // It typically contains the macro's name and the expanded code as a string
+        let name = expansion
+            .get("name")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+        let expansion_text = if let Some(expansion_str) = expansion.as_str() {
+            expansion_str.to_string()
+        } else if let Some(obj) = expansion.as_object() {
+            // Try to extract the expanded text from the response object
+            if let Some(expanded) = obj.get("expansion").and_then(|v| v.as_str()) {
+                expanded.to_string()
+            } else {
+                format!(
+                    "Macro expansion result: {}",
+                    serde_json::to_string_pretty(&expansion).unwrap_or_default()
+                )
+            }
+        } else {
+            format!("Macro expansion result: {}", expansion)
+        };
+
+        if expansion_text.trim().is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No macro expansion available at this position",
+            )]));
+        }
+
+        // rust-analyzer doesn't say where the call is, so find it among the
+        // selection ranges around the position
+        let source = lsp_client.read_file(&request.file_path).await?;
+        let call_range = match &name {
+            Some(name) => {
+                let position = lsp_types::Position {
+                    line: request.line,
+                    character: request.column,
                 };
+                lsp_client
+                    .selection_range(&request.file_path, vec![position])
+                    .await?
+                    .and_then(|ranges| ranges.into_iter().next())
+                    .and_then(|selection| macro_call_range(&source, selection, name))
+            }
+            None => None,
+        };
 
-                if expansion_text.trim().is_empty() {
-                    Ok(CallToolResult::success(vec![Content::text(
-                        "No macro expansion available at this position",
-                    )]))
-                } else {
-                    Ok(CallToolResult::success(vec![Content::text(format!(
-                        "Macro expansion:\n```rust\n{}\n```",
-                        expansion_text
-                    ))]))
+        let mut sections = Vec::new();
+        if let Some(range) = call_range {
+            let original = range_text(&source, range).unwrap_or_default();
+            sections.push(format!(
+                "Macro call at {}:\n```rust\n{}\n```",
+                display_range(range),
+                original
+            ));
+        }
+        sections.push(format!(
+            "Macro expansion:\n```rust\n{}\n```",
+            expansion_text
+        ));
+
+        if request.apply {
+            match call_range {
+                Some(range) => {
+                    let edit = TextEditInfo {
+                        range: RangeInfo {
+                            start: PositionInfo {
+                                line: range.start.line,
+                                column: range.start.character,
+                            },
+                            end: PositionInfo {
+                                line: range.end.line,
+                                column: range.end.character,
+                            },
+                        },
+                        new_text: expansion_text,
+                    };
+                    let new_text = apply_text_edits(&source, vec![edit]).map_err(|e| {
+                        McpError::invalid_params(format!("Cannot apply expansion: {}", e), None)
+                    })?;
+                    lsp_client.write_file(&request.file_path, &new_text).await?;
+                    sections.push("Replaced the macro call with its expansion.".to_string());
                 }
+                None => sections.push(
+                    "Not applied: the macro call's range could not be determined, e.g. for attribute or derive macros.".to_string(),
+                ),
             }
-            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
-                "No macro expansion available at this position",
-            )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
         }
+
+        Ok(CallToolResult::success(vec![Content::text(
+            sections.join("\n\n"),
+        )]))
     }
 
     #[tool(description = "Get document structure and symbols for code analysis")]
@@ -798,73 +3171,17 @@ impl RustAnalyzerMCP {
         &self,
         Parameters(request): Parameters<DocumentSymbolsRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
         match lsp_client.document_symbols(&request.file_path).await {
             Ok(Some(response)) => {
-                use lsp_types::DocumentSymbolResponse;
-                let symbols_text = match response {
-                    DocumentSymbolResponse::Flat(symbols) => {
-                        symbols
-                            .into_iter()
-                            .map(|symbol| {
-                                let location = &symbol.location;
-                                let file_path = location
-                                    .uri
-                                    .to_file_path()
-                                    .ok()
-                                    .and_then(|p| p.to_str().map(|s| s.to_string()))
-                                    .unwrap_or_else(|| location.uri.to_string());
-                                let kind = format!("{:?}", symbol.kind);
-                                let container = symbol
-                                    .container_name
-                                    .map(|c| format!(" (in {})", c))
-                                    .unwrap_or_default();
+                let symbols_text = format_document_symbols(response, &request.kinds);
 
-                                format!(
-                                    "• {} [{}]: {}:{}:{}{}",
-                                    symbol.name,
-                                    kind,
-                                    file_path,
-                                    location.range.start.line + 1,
-                                    location.range.start.character + 1,
-                                    container
-                                )
-                            })
-                            .collect::<Vec<_>>()
-                            .join("\n")
-                    }
-                    DocumentSymbolResponse::Nested(symbols) => {
-                        fn format_nested_symbols(symbols: Vec<lsp_types::DocumentSymbol>, indent: usize) -> String {
-                            symbols
-                                .into_iter()
-                                .map(|symbol| {
-                                    let indent_str = "  ".repeat(indent);
-                                    let kind = format!("{:?}", symbol.kind);
-                                    let range = &symbol.range;
-                                    let mut result = format!(
-                                        "{}• {} [{}]: line {}:{}",
-                                        indent_str,
-                                        symbol.name,
-                                        kind,
-                                        range.start.line + 1,
-                                        range.start.character + 1
-                                    );
-                                    
-                                    if let Some(children) = symbol.children {
-                                        if !children.is_empty() {
-                                            result.push('\n');
-                                            result.push_str(&format_nested_symbols(children, indent + 1));
-                                        }
-                                    }
-                                    result
-                                })
-                                .collect::<Vec<_>>()
-                                .join("\n")
-                        }
-                        format_nested_symbols(symbols, 0)
-                    }
-                };
+                if symbols_text.is_empty() {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        "No symbols found in document",
+                    )]));
+                }
 
                 Ok(CallToolResult::success(vec![Content::text(format!(
                     "Document symbols:\n{}",
@@ -874,19 +3191,105 @@ impl RustAnalyzerMCP {
             Ok(None) => Ok(CallToolResult::success(vec![Content::text(
                 "No symbols found in document",
             )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+            Err(e) => Err(e.into()),
         }
     }
 
+    #[tool(description = "Get the nesting path of symbols enclosing a position")]
+    async fn breadcrumb(
+        &self,
+        Parameters(request): Parameters<BreadcrumbRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        let position = lsp_types::Position {
+            line: request.line,
+            character: request.column,
+        };
+        let names = lsp_client
+            .document_symbols(&request.file_path)
+            .await?
+            .map(|response| breadcrumb(response, position))
+            .unwrap_or_default();
+
+        if names.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "{} is not inside any symbol",
+                display_position(position)
+            ))]));
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(
+            names.join(" > "),
+        )]))
+    }
+
+    #[tool(description = "Get a file's symbol outline and diagnostics in one call")]
+    async fn file_overview(
+        &self,
+        Parameters(request): Parameters<FileOverviewRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        let (symbols, diagnostics) = tokio::join!(
+            lsp_client.document_symbols(&request.file_path),
+            lsp_client.diagnostics(&request.file_path),
+        );
+        let outline = match symbols? {
+            Some(response) => format_document_symbols(response, &None),
+            None => String::new(),
+        };
+        let diagnostics = diagnostics?;
+
+        let total = diagnostics.len();
+        let diagnostics = diagnostics
+            .into_iter()
+            .filter(|diag| {
+                request
+                    .min_severity
+                    .is_none_or(|min| min.allows(diag.severity))
+            })
+            .map(format_diagnostic)
+            .collect::<Vec<_>>();
+
+        let outline_section = if outline.is_empty() {
+            "No symbols found in document".to_string()
+        } else {
+            outline
+        };
+        let diagnostics_section = if diagnostics.is_empty() {
+            "No diagnostics found".to_string()
+        } else {
+            diagnostics.join("\n")
+        };
+        let hidden = total - diagnostics.len();
+        let hidden_note = if hidden > 0 {
+            format!(" ({} below the severity filter hidden)", hidden)
+        } else {
+            String::new()
+        };
+
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "## Outline\n{}\n\n## Diagnostics{}\n{}",
+            outline_section, hidden_note, diagnostics_section
+        ))]))
+    }
+
     #[tool(description = "Get function signature help for parameter assistance")]
     async fn signature_help(
         &self,
         Parameters(request): Parameters<SignatureHelpRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
         match lsp_client
-            .signature_help(&request.file_path, request.line, request.column)
+            .signature_help(
+                &request.file_path,
+                request.line,
+                request.column,
+                request.trigger_character,
+                request.is_retrigger,
+            )
             .await
         {
             Ok(Some(help)) => {
@@ -895,19 +3298,27 @@ impl RustAnalyzerMCP {
                         "No signature help available",
                     )]))
                 } else {
+                    let active_signature = help.active_signature.unwrap_or(0) as usize;
+                    let multiple = help.signatures.len() > 1;
                     let signatures_text = help
                         .signatures
                         .into_iter()
                         .enumerate()
                         .map(|(i, sig)| {
-                            let active_param = help.active_parameter.unwrap_or(0) as usize;
-                            let mut signature = format!("{}. {}", i + 1, sig.label);
-                            
-                            if let Some(doc) = sig.documentation.as_ref() {
-                                let doc_text = match doc {
-                                    lsp_types::Documentation::String(s) => s.clone(),
-                                    lsp_types::Documentation::MarkupContent(mc) => mc.value.clone(),
-                                };
+                            // A signature's own active parameter takes precedence
+                            let active_param =
+                                sig.active_parameter.or(help.active_parameter).unwrap_or(0)
+                                    as usize;
+                            let active_marker = if multiple && i == active_signature {
+                                " (active)"
+                            } else {
+                                ""
+                            };
+                            let mut signature =
+                                format!("{}. {}{}", i + 1, sig.label, active_marker);
+
+                            if let Some(doc) = sig.documentation.clone() {
+                                let doc_text = request.format.render_documentation(doc);
                                 if !doc_text.is_empty() {
                                     signature.push_str(&format!("\n   {}", doc_text));
                                 }
@@ -924,11 +3335,8 @@ impl RustAnalyzerMCP {
                                         }
                                     };
                                     signature.push_str(&format!("\n{}{}", marker, label_text));
-                                    if let Some(doc) = &param.documentation {
-                                        let doc_text = match doc {
-                                            lsp_types::Documentation::String(s) => s.clone(),
-                                            lsp_types::Documentation::MarkupContent(mc) => mc.value.clone(),
-                                        };
+                                    if let Some(doc) = param.documentation.clone() {
+                                        let doc_text = request.format.render_documentation(doc);
                                         if !doc_text.is_empty() {
                                             signature.push_str(&format!(" - {}", doc_text));
                                         }
@@ -949,7 +3357,7 @@ impl RustAnalyzerMCP {
             Ok(None) => Ok(CallToolResult::success(vec![Content::text(
                 "No signature help available",
             )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -958,7 +3366,7 @@ impl RustAnalyzerMCP {
         &self,
         Parameters(request): Parameters<DocumentHighlightRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
         match lsp_client
             .document_highlight(&request.file_path, request.line, request.column)
@@ -977,14 +3385,7 @@ impl RustAnalyzerMCP {
                                 .kind
                                 .map(|k| format!(" ({:?})", k))
                                 .unwrap_or_default();
-                            format!(
-                                "Line {}:{}-{}:{}{}",
-                                highlight.range.start.line + 1,
-                                highlight.range.start.character + 1,
-                                highlight.range.end.line + 1,
-                                highlight.range.end.character + 1,
-                                kind
-                            )
+                            format!("{}{}", display_range(highlight.range), kind)
                         })
                         .collect::<Vec<_>>()
                         .join("\n");
@@ -998,7 +3399,7 @@ impl RustAnalyzerMCP {
             Ok(None) => Ok(CallToolResult::success(vec![Content::text(
                 "No highlights found at this position",
             )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -1007,7 +3408,7 @@ impl RustAnalyzerMCP {
         &self,
         Parameters(request): Parameters<SelectionRangeRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
         let positions: Vec<lsp_types::Position> = request
             .positions
@@ -1018,6 +3419,12 @@ impl RustAnalyzerMCP {
             })
             .collect();
 
+        let source = if request.include_text {
+            Some(lsp_client.read_file(&request.file_path).await?)
+        } else {
+            None
+        };
+
         match lsp_client
             .selection_range(&request.file_path, positions)
             .await
@@ -1037,15 +3444,18 @@ impl RustAnalyzerMCP {
                             loop {
                                 let indent = "  ".repeat(level);
                                 result.push_str(&format!(
-                                    "\n{}Level {}: Line {}:{}-{}:{}",
+                                    "\n{}Level {}: {}",
                                     indent,
                                     level,
-                                    range.range.start.line + 1,
-                                    range.range.start.character + 1,
-                                    range.range.end.line + 1,
-                                    range.range.end.character + 1
+                                    display_range(range.range)
                                 ));
-                                
+                                let text = source
+                                    .as_deref()
+                                    .and_then(|source| range_text(source, range.range));
+                                if let Some(text) = text {
+                                    result.push_str(&format_selection_text(&text, &indent));
+                                }
+
                                 if let Some(parent) = range.parent {
                                     range = *parent;
                                     level += 1;
@@ -1058,164 +3468,726 @@ impl RustAnalyzerMCP {
                         .collect::<Vec<_>>()
                         .join("\n\n");
 
-                    Ok(CallToolResult::success(vec![Content::text(format!(
-                        "Selection ranges:\n{}",
-                        ranges_text
-                    ))]))
-                }
+                    Ok(CallToolResult::success(vec![Content::text(format!(
+                        "Selection ranges:\n{}",
+                        ranges_text
+                    ))]))
+                }
+            }
+            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
+                "No selection ranges found",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Find runnable items (tests, benchmarks, executables) with cargo commands")]
+    async fn runnables(
+        &self,
+        Parameters(request): Parameters<RunnablesRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        if !lsp_client.is_rust_analyzer() {
+            return Ok(unsupported_by_backend("runnables"));
+        }
+
+        match lsp_client.runnables(&request.file_path).await {
+            Ok(Some(runnables)) => {
+                if let Some(array) = runnables.as_array() {
+                    if array.is_empty() {
+                        Ok(CallToolResult::success(vec![Content::text(
+                            "No runnable items found",
+                        )]))
+                    } else {
+                        let runnables_text = array
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(i, runnable)| {
+                                let obj = runnable.as_object()?;
+                                let label = obj.get("label")?.as_str().unwrap_or("Unknown");
+                                let kind = obj.get("kind")?.as_str().unwrap_or("Unknown");
+                                let location = obj.get("location")?;
+                                let range: lsp_types::Range =
+                                    serde_json::from_value(location.get("range")?.clone()).ok()?;
+
+                                // Extract cargo command if available
+                                let cargo_cmd = runnable_cargo_command(runnable)
+                                    .map(|cmd| format!(" → {}", cmd))
+                                    .unwrap_or_default();
+
+                                Some(format!(
+                                    "{}. {} [{}] at {}{}",
+                                    i + 1,
+                                    label,
+                                    kind,
+                                    display_position(range.start),
+                                    cargo_cmd
+                                ))
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n");
+
+                        if runnables_text.is_empty() {
+                            Ok(CallToolResult::success(vec![Content::text(
+                                "No valid runnable items found",
+                            )]))
+                        } else {
+                            Ok(CallToolResult::success(vec![Content::text(format!(
+                                "Runnable items:\n{}",
+                                runnables_text
+                            ))]))
+                        }
+                    }
+                } else {
+                    Ok(CallToolResult::success(vec![Content::text(
+                        "Unexpected runnables response format",
+                    )]))
+                }
+            }
+            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
+                "No runnable items found",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Find all implementations of a trait at the given position")]
+    async fn implementations(
+        &self,
+        Parameters(request): Parameters<ImplementationsRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client
+            .implementations(&request.file_path, request.line, request.column)
+            .await
+        {
+            Ok(Some(locations)) => {
+                if locations.is_empty() {
+                    Ok(CallToolResult::success(vec![Content::text(
+                        "No implementations found",
+                    )]))
+                } else {
+                    let implementations_text = locations
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, loc)| {
+                            let path = loc
+                                .uri
+                                .to_file_path()
+                                .ok()
+                                .and_then(|p| p.to_str().map(|s| s.to_string()))
+                                .unwrap_or_else(|| loc.uri.to_string());
+                            format!(
+                                "{}. Implementation at: {}:{}",
+                                i + 1,
+                                path,
+                                display_position(loc.range.start)
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    Ok(CallToolResult::success(vec![Content::text(format!(
+                        "Found implementations:\n{}",
+                        implementations_text
+                    ))]))
+                }
+            }
+            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
+                "No implementations found",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "List a file's impl blocks by type and where its traits are implemented")]
+    async fn list_impls(
+        &self,
+        Parameters(request): Parameters<ListImplsRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        let symbols = match lsp_client.document_symbols(&request.file_path).await? {
+            Some(response) => flatten_document_symbols(response),
+            None => Vec::new(),
+        };
+
+        // Impl blocks in this file, keyed by self type
+        let mut impls_by_type: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut traits = Vec::new();
+        for (name, kind, position) in symbols {
+            if kind == lsp_types::SymbolKind::INTERFACE {
+                traits.push((name, position));
+            } else if let Some((trait_name, self_ty)) = parse_impl_header(&name) {
+                impls_by_type.entry(self_ty).or_default().push(format!(
+                    "  • {}: line {}",
+                    trait_name.as_deref().unwrap_or("inherent"),
+                    display_position(position)
+                ));
+            }
+        }
+
+        let mut sections = Vec::new();
+        if !impls_by_type.is_empty() {
+            let blocks = impls_by_type
+                .into_iter()
+                .map(|(self_ty, impls)| format!("{}\n{}", self_ty, impls.join("\n")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            sections.push(format!("Impl blocks in this file:\n{}", blocks));
+        }
+
+        // Where the traits declared here are implemented, in any file
+        let mut trait_blocks = Vec::new();
+        for (trait_name, position) in traits {
+            let locations = lsp_client
+                .implementations(&request.file_path, position.line, position.character)
+                .await?
+                .unwrap_or_default();
+            if locations.is_empty() {
+                continue;
+            }
+            let locations = locations
+                .into_iter()
+                .map(|loc| {
+                    let path = loc
+                        .uri
+                        .to_file_path()
+                        .ok()
+                        .and_then(|p| p.to_str().map(|s| s.to_string()))
+                        .unwrap_or_else(|| loc.uri.to_string());
+                    format!("  • {}:{}", path, display_position(loc.range.start))
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            trait_blocks.push(format!("{}\n{}", trait_name, locations));
+        }
+        if !trait_blocks.is_empty() {
+            sections.push(format!(
+                "Implementations of traits declared here:\n{}",
+                trait_blocks.join("\n")
+            ));
+        }
+
+        if sections.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No impl blocks found",
+            )]));
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(
+            sections.join("\n\n"),
+        )]))
+    }
+
+    #[tool(description = "List links embedded in a document, such as URLs in doc comments")]
+    async fn document_links(
+        &self,
+        Parameters(request): Parameters<DocumentLinksRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client.document_links(&request.file_path).await {
+            Ok(Some(links)) if !links.is_empty() => {
+                let mut links_text = Vec::new();
+                for link in links {
+                    // Targets may be computed lazily, resolve them when the server allows it
+                    let needs_resolve = link.target.is_none();
+                    let link = if needs_resolve && lsp_client.supports_document_link_resolve() {
+                        lsp_client.resolve_document_link(link).await?
+                    } else {
+                        link
+                    };
+
+                    let target = link
+                        .target
+                        .map(|t| t.to_string())
+                        .unwrap_or_else(|| "(unresolved)".to_string());
+                    let tooltip = link
+                        .tooltip
+                        .map(|t| format!(" ({})", t))
+                        .unwrap_or_default();
+                    links_text.push(format!(
+                        "{} → {}{}",
+                        display_range(link.range),
+                        target,
+                        tooltip
+                    ));
+                }
+
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Document links:\n{}",
+                    links_text.join("\n")
+                ))]))
+            }
+            Ok(_) => Ok(CallToolResult::success(vec![Content::text(
+                "No document links found",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "List code lenses (run/debug test, references) with their commands")]
+    async fn code_lenses(
+        &self,
+        Parameters(request): Parameters<CodeLensesRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client.code_lenses(&request.file_path).await {
+            Ok(Some(lenses)) if !lenses.is_empty() => {
+                let mut lenses_text = Vec::new();
+                for lens in lenses {
+                    // Reference/implementation lenses are usually resolved lazily
+                    let needs_resolve = lens.command.is_none();
+                    let lens = if needs_resolve && lsp_client.supports_code_lens_resolve() {
+                        lsp_client.resolve_code_lens(lens).await?
+                    } else {
+                        lens
+                    };
+
+                    let range = display_range(lens.range);
+                    match lens.command {
+                        Some(command) => {
+                            let cargo_cmd = command
+                                .arguments
+                                .as_ref()
+                                .and_then(|args| args.first())
+                                .and_then(runnable_cargo_command)
+                                .map(|cmd| format!(" → {}", cmd))
+                                .unwrap_or_default();
+                            lenses_text.push(format!(
+                                "{}: {} (command: {}){}",
+                                range, command.title, command.command, cargo_cmd
+                            ));
+                        }
+                        None => lenses_text.push(format!("{}: (unresolved lens)", range)),
+                    }
+                }
+
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Code lenses:\n{}",
+                    lenses_text.join("\n")
+                ))]))
+            }
+            Ok(_) => Ok(CallToolResult::success(vec![Content::text(
+                "No code lenses found",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Evaluate the function at a position with rust-analyzer's interpreter")]
+    async fn interpret_function(
+        &self,
+        Parameters(request): Parameters<InterpretFunctionRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        if !lsp_client.is_rust_analyzer() {
+            return Ok(unsupported_by_backend("interpret_function"));
+        }
+
+        match lsp_client
+            .interpret_function(&request.file_path, request.line, request.column)
+            .await
+        {
+            Ok(Some(output)) if !output.trim().is_empty() => {
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Interpreter output:\n```\n{}\n```",
+                    output.trim_end()
+                ))]))
+            }
+            Ok(_) => Ok(CallToolResult::success(vec![Content::text(
+                "No interpretable function at this position",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Dump rust-analyzer's item tree for a file (rust-analyzer only)")]
+    async fn view_item_tree(
+        &self,
+        Parameters(request): Parameters<ViewItemTreeRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        if !lsp_client.is_rust_analyzer() {
+            return Ok(unsupported_by_backend("view_item_tree"));
+        }
+
+        let Some(tree) = lsp_client.view_item_tree(&request.file_path).await? else {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No item tree available for this file",
+            )]));
+        };
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Item tree for {}:\n```\n{}\n```",
+            request.file_path,
+            tree.trim_end()
+        ))]))
+    }
+
+    #[tool(description = "Show a file's text as rust-analyzer sees it (rust-analyzer only)")]
+    async fn view_file_text(
+        &self,
+        Parameters(request): Parameters<ViewFileTextRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        if !lsp_client.is_rust_analyzer() {
+            return Ok(unsupported_by_backend("view_file_text"));
+        }
+
+        let Some(text) = lsp_client.view_file_text(&request.file_path).await? else {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "rust-analyzer has no text for this file",
+            )]));
+        };
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "rust-analyzer's text for {}:\n```rust\n{}\n```",
+            request.file_path,
+            text.trim_end()
+        ))]))
+    }
+
+    #[tool(description = "Get cross-index identifiers (monikers) for the symbol at a position")]
+    async fn moniker(
+        &self,
+        Parameters(request): Parameters<MonikerRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        if !lsp_client.supports_moniker() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "The language server does not support monikers",
+            )]));
+        }
+
+        match lsp_client
+            .moniker(&request.file_path, request.line, request.column)
+            .await
+        {
+            Ok(Some(monikers)) if !monikers.is_empty() => {
+                let monikers_text = monikers
+                    .into_iter()
+                    .map(|moniker| {
+                        let kind = moniker
+                            .kind
+                            .map(|k| format!(", kind: {:?}", k))
+                            .unwrap_or_default();
+                        format!(
+                            "- {} (scheme: {}, unique: {:?}{})",
+                            moniker.identifier, moniker.scheme, moniker.unique, kind
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Monikers:\n{}",
+                    monikers_text
+                ))]))
+            }
+            Ok(_) => Ok(CallToolResult::success(vec![Content::text(
+                "No monikers found at this position",
+            )])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Change language server settings at runtime without restarting")]
+    async fn configure(
+        &self,
+        Parameters(request): Parameters<ConfigureRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        if !request.settings.is_object() {
+            return Err(McpError::invalid_params(
+                "settings must be a JSON object of language server settings",
+                None,
+            ));
+        }
+
+        let lsp_client = self.client().await?;
+        let settings = lsp_client.configure(request.settings).await?;
+        let settings = serde_json::to_string_pretty(&settings).map_err(LspError::from)?;
+
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Effective language server settings:\n```json\n{}\n```",
+            settings
+        ))]))
+    }
+
+    #[tool(description = "Send a raw LSP request and return the raw JSON result (debugging)")]
+    async fn lsp_raw(
+        &self,
+        Parameters(request): Parameters<LspRawRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client
+            .raw_request(&request.method, request.params)
+            .await
+        {
+            Ok(result) => {
+                let result = serde_json::to_string_pretty(&result).map_err(LspError::from)?;
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "```json\n{}\n```",
+                    result
+                ))]))
+            }
+            // Hand the server's error object back as-is instead of mapping it
+            Err(LspError::Protocol { error, .. }) => {
+                let error = serde_json::json!({
+                    "code": error.code,
+                    "message": error.message,
+                    "data": error.data,
+                });
+                let error = serde_json::to_string_pretty(&error).map_err(LspError::from)?;
+                Ok(CallToolResult::error(vec![Content::text(format!(
+                    "```json\n{}\n```",
+                    error
+                ))]))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tool(description = "Check that the language server is alive and responding")]
+    async fn ping(
+        &self,
+        Parameters(request): Parameters<PingRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let started = std::time::Instant::now();
+        let timeout = std::time::Duration::from_millis(request.timeout_ms);
+
+        // The timeout also covers waiting for a restart in progress
+        let result = tokio::time::timeout(timeout, async {
+            // Checking on a server stopped for being idle shouldn't start it
+            let lsp_client = self.lsp_client.lock().await.clone();
+            match lsp_client {
+                Some(lsp_client) => match lsp_client.ping().await {
+                    Ok(()) => Ok(Some(lsp_client.work_progress().await)),
+                    Err(e) => Err(e),
+                },
+                None => Ok(None),
+            }
+        })
+        .await;
+
+        let status = match result {
+            Ok(Ok(None)) => IDLE_STOPPED_STATUS.to_string(),
+            Ok(Ok(Some(progress))) => format!(
+                "Server responded in {} ms\nStatus: {}",
+                started.elapsed().as_millis(),
+                work_status(&progress)
+            ),
+            Ok(Err(e)) => format!("Server is not responding: {}", e),
+            Err(_) => format!("Server did not respond within {} ms", request.timeout_ms),
+        };
+
+        Ok(CallToolResult::success(vec![Content::text(status)]))
+    }
+
+    #[tool(description = "Show the language server's state and open document count")]
+    async fn server_status(
+        &self,
+        Parameters(_request): Parameters<ServerStatusRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.lsp_client.lock().await.clone();
+        let Some(lsp_client) = lsp_client else {
+            return Ok(CallToolResult::success(vec![Content::text(
+                IDLE_STOPPED_STATUS,
+            )]));
+        };
+
+        let server = lsp_client
+            .server_info()
+            .map(|info| match &info.version {
+                Some(version) => format!("{} {}", info.name, version),
+                None => info.name.clone(),
+            })
+            .unwrap_or_else(|| "unknown".to_string());
+        let open = lsp_client.get_opened_documents_count().await;
+        let open = match lsp_client.max_open_documents() {
+            0 => format!("{} (no limit)", open),
+            max => format!("{} of {}", open, max),
+        };
+        let (hits, misses) = lsp_client.sync_stats();
+        let progress = lsp_client.work_progress().await;
+
+        let mut lines = vec![
+            format!("Server: {}", server),
+            format!("Status: {}", work_status(&progress)),
+            format!("Open documents: {}", open),
+            format!("Document sync: {} cache hits, {} resyncs", hits, misses),
+        ];
+        if progress.is_empty() {
+            lines.push("Progress: idle".to_string());
+        } else {
+            lines.push("Progress:".to_string());
+            for work in &progress {
+                lines.push(format!("  {}", describe_work(work)));
             }
-            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
-                "No selection ranges found",
-            )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
         }
+        Ok(CallToolResult::success(vec![Content::text(
+            lines.join("\n"),
+        )]))
     }
 
-    #[tool(description = "Find runnable items (tests, benchmarks, executables) with cargo commands")]
-    async fn runnables(
+    #[tool(description = "List the commands the language server can execute")]
+    async fn list_commands(
         &self,
-        Parameters(request): Parameters<RunnablesRequest>,
+        Parameters(_request): Parameters<ListCommandsRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
-        match lsp_client.runnables(&request.file_path).await {
-            Ok(Some(runnables)) => {
-                if let Some(array) = runnables.as_array() {
-                    if array.is_empty() {
-                        Ok(CallToolResult::success(vec![Content::text(
-                            "No runnable items found",
-                        )]))
-                    } else {
-                        let runnables_text = array
-                            .iter()
-                            .enumerate()
-                            .filter_map(|(i, runnable)| {
-                                let obj = runnable.as_object()?;
-                                let label = obj.get("label")?.as_str().unwrap_or("Unknown");
-                                let kind = obj.get("kind")?.as_str().unwrap_or("Unknown");
-                                let location = obj.get("location")?;
-                                let range = location.get("range")?;
-                                let start = range.get("start")?;
-                                let line = start.get("line")?.as_u64().unwrap_or(0) + 1;
-                                let character = start.get("character")?.as_u64().unwrap_or(0) + 1;
+        let commands = lsp_client.commands();
+        if commands.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "The language server advertises no commands",
+            )]));
+        }
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Available commands:\n{}",
+            commands
+                .iter()
+                .map(|command| format!("  {}", command))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))]))
+    }
 
-                                // Extract cargo command if available
-                                let cargo_cmd = if let Some(args) = obj.get("args") {
-                                    if let Some(cargo_args) = args.get("cargoArgs") {
-                                        if let Some(cargo_array) = cargo_args.as_array() {
-                                            let cmd_parts: Vec<String> = cargo_array
-                                                .iter()
-                                                .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                                                .collect();
-                                            if !cmd_parts.is_empty() {
-                                                format!(" → cargo {}", cmd_parts.join(" "))
-                                            } else {
-                                                String::new()
-                                            }
-                                        } else {
-                                            String::new()
-                                        }
-                                    } else {
-                                        String::new()
-                                    }
-                                } else {
-                                    String::new()
-                                };
+    #[tool(description = "Execute a command advertised by the language server")]
+    async fn execute_command(
+        &self,
+        Parameters(request): Parameters<ExecuteCommandRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
 
-                                Some(format!(
-                                    "{}. {} [{}] at line {}:{}{}",
-                                    i + 1, label, kind, line, character, cargo_cmd
-                                ))
-                            })
-                            .collect::<Vec<_>>()
-                            .join("\n");
+        if !lsp_client.commands().contains(&request.command) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "Unknown command '{}'; see list_commands for the available ones",
+                    request.command
+                ),
+                None,
+            ));
+        }
 
-                        if runnables_text.is_empty() {
-                            Ok(CallToolResult::success(vec![Content::text(
-                                "No valid runnable items found",
-                            )]))
-                        } else {
-                            Ok(CallToolResult::success(vec![Content::text(format!(
-                                "Runnable items:\n{}",
-                                runnables_text
-                            ))]))
-                        }
-                    }
-                } else {
-                    Ok(CallToolResult::success(vec![Content::text(
-                        "Unexpected runnables response format",
-                    )]))
-                }
+        match lsp_client
+            .execute_command(&request.command, request.arguments)
+            .await?
+        {
+            Some(result) if !result.is_null() => {
+                let result = serde_json::to_string_pretty(&result).map_err(LspError::from)?;
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "```json\n{}\n```",
+                    result
+                ))]))
             }
-            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
-                "No runnable items found",
-            )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+            _ => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Executed {}",
+                request.command
+            ))])),
         }
     }
 
-    #[tool(description = "Find all implementations of a trait at the given position")]
-    async fn implementations(
+    #[tool(description = "Summarize which LSP features the language server supports")]
+    async fn server_capabilities(
         &self,
-        Parameters(request): Parameters<ImplementationsRequest>,
+        Parameters(_request): Parameters<ServerCapabilitiesRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let lsp_client = self.lsp_client.lock().await;
+        let lsp_client = self.client().await?;
 
-        match lsp_client
-            .implementations(&request.file_path, request.line, request.column)
-            .await
-        {
-            Ok(Some(locations)) => {
-                if locations.is_empty() {
-                    Ok(CallToolResult::success(vec![Content::text(
-                        "No implementations found",
-                    )]))
-                } else {
-                    let implementations_text = locations
-                        .into_iter()
-                        .enumerate()
-                        .map(|(i, loc)| {
-                            let path = loc
-                                .uri
-                                .to_file_path()
-                                .ok()
-                                .and_then(|p| p.to_str().map(|s| s.to_string()))
-                                .unwrap_or_else(|| loc.uri.to_string());
-                            format!(
-                                "{}. Implementation at: {}:{}:{}",
-                                i + 1,
-                                path,
-                                loc.range.start.line + 1,
-                                loc.range.start.character + 1
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n");
+        let capabilities = serde_json::to_value(lsp_client.server_capabilities())
+            .map_err(|e| McpError::internal_error(format!("LSP error: {}", e), None))?;
+        // A capability is supported when present and not explicitly `false`
+        let supported = |key: &str| {
+            capabilities
+                .get(key)
+                .is_some_and(|v| !v.is_null() && v != &serde_json::Value::Bool(false))
+        };
 
-                    Ok(CallToolResult::success(vec![Content::text(format!(
-                        "Found implementations:\n{}",
-                        implementations_text
-                    ))]))
-                }
+        let mut lines = Vec::new();
+        if let Some(info) = lsp_client.server_info() {
+            lines.push(format!(
+                "Server: {} {}",
+                info.name,
+                info.version.as_deref().unwrap_or("")
+            ));
+        }
+
+        lines.push("Capabilities:".to_string());
+        for (key, tool) in CAPABILITY_TOOLS {
+            let marker = if supported(key) { "✓" } else { "✗" };
+            let tool = tool
+                .map(|tool| format!(" (tool: {})", tool))
+                .unwrap_or_default();
+            lines.push(format!("{} {}{}", marker, key, tool));
+        }
+
+        for (key, label) in [
+            ("completionProvider", "Completion trigger characters"),
+            ("signatureHelpProvider", "Signature help trigger characters"),
+        ] {
+            if let Some(chars) = capabilities
+                .get(key)
+                .and_then(|c| c.get("triggerCharacters"))
+                .and_then(|c| c.as_array())
+            {
+                let chars: Vec<&str> = chars.iter().filter_map(|c| c.as_str()).collect();
+                lines.push(format!("{}: {}", label, chars.join(" ")));
             }
-            Ok(None) => Ok(CallToolResult::success(vec![Content::text(
-                "No implementations found",
-            )])),
-            Err(e) => Err(McpError::internal_error(format!("LSP error: {}", e), None)),
+        }
+
+        if let Some(experimental) = capabilities.get("experimental").and_then(|e| e.as_object()) {
+            let keys: Vec<&str> = experimental.keys().map(|k| k.as_str()).collect();
+            lines.push(format!("Experimental: {}", keys.join(", ")));
+        }
+
+        Ok(CallToolResult::success(vec![Content::text(
+            lines.join("\n"),
+        )]))
+    }
+
+    #[tool(description = "Close a document so the language server can free its memory")]
+    async fn close_document(
+        &self,
+        Parameters(request): Parameters<CloseDocumentRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let lsp_client = self.client().await?;
+
+        match lsp_client.close_document(&request.file_path).await {
+            Ok(true) => {
+                let remaining = lsp_client.get_opened_documents_count().await;
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Closed document: {} ({} documents still open)",
+                    request.file_path, remaining
+                ))]))
+            }
+            Ok(false) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Document was not open: {}",
+                request.file_path
+            ))])),
+            Err(e) => Err(e.into()),
         }
     }
 }
 
-#[tool_handler]
 impl ServerHandler for RustAnalyzerMCP {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
             protocol_version: ProtocolVersion::V_2024_11_05,
             capabilities: ServerCapabilities::builder()
                 .enable_tools()
+                .enable_prompts()
+                .enable_resources()
                 .build(),
             server_info: Implementation::from_build_env(),
-            instructions: Some("This server provides rust-analyzer functionality through MCP tools. Available tools: 'hover' for type information, 'completion' for code completions, 'diagnostics' for compile errors, 'goto_definition' to find definitions, 'find_references' to find all references, 'format_document' to format code, 'rename' to rename symbols across the workspace, 'code_actions' to get quick fixes and refactorings, 'workspace_symbols' to search symbols across the workspace, 'inlay_hints' to get type and parameter hints, 'expand_macro' to expand Rust macros, 'document_symbols' for code structure analysis, 'signature_help' for function parameter assistance, 'document_highlight' for symbol occurrence highlighting, 'selection_range' for smart selection expansion, 'runnables' to find tests, benchmarks, and executables, and 'implementations' to find all implementations of a trait.".to_string()),
+            instructions: Some("This server provides rust-analyzer functionality through MCP tools. Available tools: 'hover' for type information, 'completion' for code completions, 'resolve_completion' for the full details of a completion item, 'diagnostics' for compile errors, 'goto_definition' to find definitions, 'definition_source' to read the full item a symbol is defined by, 'find_references' to find all references, 'usage_summary' to count references per file, 'explain_symbol' to get a symbol's type, definition and usage in one call, 'symbol_path' to get a symbol's crate/module path for use statements, 'format_document' to format code, 'format_range' to format only a selection, 'apply_edits' to write explicit text edits to a file, 'rename' to rename symbols across the workspace, 'prepare_and_rename' to validate and preview a rename in one call, 'code_actions' to get quick fixes and refactorings, 'apply_code_action' to apply one of them, 'fix_all' to apply the quick fixes for every diagnostic in a file, 'workspace_symbols' to search symbols across the workspace, 'inlay_hints' to get type and parameter hints, 'expand_macro' to expand Rust macros, 'document_symbols' for code structure analysis, 'breadcrumb' to see which symbols enclose a position, 'file_overview' to get a file's outline and diagnostics in one call, 'signature_help' for function parameter assistance, 'document_highlight' for symbol occurrence highlighting, 'selection_range' for smart selection expansion, 'runnables' to find tests, benchmarks, and executables, 'implementations' to find all implementations of a trait, 'list_impls' to map a file's impl blocks by type, 'document_links' to list links embedded in a document, 'code_lenses' to see what can be run or inspected per line, 'interpret_function' to evaluate a function with rust-analyzer's interpreter, 'view_item_tree' to dump a file's item tree, 'view_file_text' to see the file text rust-analyzer is analyzing, 'moniker' to get cross-index symbol identifiers, 'configure' to change language server settings at runtime, 'list_commands' and 'execute_command' to run the server's commands, 'server_capabilities' to see which features the backend supports, 'ping' to check that the server is responsive, 'server_status' to see its state and open document count, and 'close_document' to release documents that are no longer needed. The workspace's .rs files and Cargo.toml manifests are also available as file:// resources, and the 'explain_symbol' and 'triage_diagnostics' prompts chain these tools for common workflows.".to_string()),
         }
     }
 
@@ -1226,29 +4198,738 @@ impl ServerHandler for RustAnalyzerMCP {
     ) -> Result<InitializeResult, McpError> {
         Ok(self.get_info())
     }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        // Dropping the tool's future on cancellation also cancels its in-flight
+        // LSP request, so rust-analyzer stops working on it
+        let ct = context.ct.clone();
+        let tcc = rmcp::handler::server::tool::ToolCallContext::new(self, request, context);
+        tokio::select! {
+            result = self.tool_router.call(tcc) => result,
+            _ = ct.cancelled() => Err(McpError::internal_error("Request cancelled by client", None)),
+        }
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult {
+            tools: self.tool_router.list_all(),
+            next_cursor: None,
+        })
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, McpError> {
+        Ok(ListPromptsResult {
+            prompts: prompts::list(),
+            next_cursor: None,
+        })
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, McpError> {
+        prompts::get(&request.name, request.arguments)
+    }
+
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        let roots = self.workspace_roots.clone();
+        let files = tokio::task::spawn_blocking(move || resources::workspace_files(&roots))
+            .await
+            .map_err(|e| {
+                McpError::internal_error(format!("Failed to list workspace files: {}", e), None)
+            })?;
+
+        let resources = files
+            .iter()
+            .filter_map(|file| {
+                let uri = lsp_types::Url::from_file_path(file).ok()?;
+                let name = resources::display_name(&self.workspace_roots, file);
+                let mut resource = RawResource::new(uri.to_string(), name);
+                resource.mime_type = Some(resources::mime_type(file).to_string());
+                Some(resource.no_annotation())
+            })
+            .collect();
+
+        Ok(ListResourcesResult {
+            resources,
+            next_cursor: None,
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        // Only serve the kinds of files we list, and only from inside the workspace
+        let path = lsp_types::Url::parse(&request.uri)
+            .ok()
+            .and_then(|uri| uri.to_file_path().ok())
+            .filter(|path| {
+                resources::is_resource_file(path)
+                    && config::is_in_workspace(&self.workspace_roots, path)
+            })
+            .ok_or_else(|| {
+                McpError::resource_not_found(format!("Unknown resource: {}", request.uri), None)
+            })?;
+
+        let text = tokio::fs::read_to_string(&path).await.map_err(|e| {
+            McpError::resource_not_found(format!("Cannot read {}: {}", request.uri, e), None)
+        })?;
+
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::text(text, request.uri)],
+        })
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into()))
-        .with_writer(std::io::stderr)
-        .with_ansi(false)
-        .init();
+    let config = Config::from_args(std::env::args().skip(1))?;
+
+    // stdout belongs to the MCP stdio transport, so logs go to stderr or a file
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env().add_directive(config.log_level.into()))
+        .with_ansi(false);
+    match &config.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            subscriber.with_writer(Arc::new(file)).init();
+        }
+        None => subscriber.with_writer(std::io::stderr).init(),
+    }
 
     info!("Starting rust-analyzer MCP server");
+    let server = RustAnalyzerMCP::new(&config).await?;
 
-    let workspace_root = std::env::current_dir()?;
-    let service = RustAnalyzerMCP::new(workspace_root)
-        .await?
-        .serve(stdio())
-        .await
-        .inspect_err(|e| {
-            error!("serving error: {:?}", e);
-        })?;
+    match config.transport {
+        Transport::Stdio => {
+            let service = server.serve(stdio()).await.inspect_err(|e| {
+                error!("serving error: {:?}", e);
+            })?;
 
-    info!("MCP server is running");
-    service.waiting().await?;
+            info!("MCP server is running");
+            service.waiting().await?;
+        }
+        Transport::Sse => {
+            // Every connection shares the LspClient but lists its own items
+            let ct = SseServer::serve(config.bind)
+                .await?
+                .with_service(move || server.session());
+
+            info!("MCP server is running on http://{}/sse", config.bind);
+            tokio::signal::ctrl_c().await?;
+            ct.cancel();
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_client::mock::{self, MockServer};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    /// A server backed by the mock language server, with `files` in its
+    /// workspace.
+    async fn mock_server(
+        files: &[(&str, &str)],
+        capabilities: Value,
+    ) -> (RustAnalyzerMCP, MockServer) {
+        let root = mock::workspace(files);
+        let config = Config::from_args([root.display().to_string()]).unwrap();
+        let (lsp_client, server) = mock::connect(&root, capabilities).await;
+        (RustAnalyzerMCP::with_client(&config, lsp_client), server)
+    }
+
+    #[test]
+    fn columns_are_utf16_units() {
+        // The emoji takes two UTF-16 units, so `café` starts at column 10
+        let line = "s = \"😀\"; café.len()";
+        assert_eq!(identifier_at(line, 12), "café");
+        assert_eq!(identifier_prefix(line, 12), "ca");
+        assert_eq!(identifier_prefix(line, 14), "café");
+
+        let text = "a😀b\r\nc";
+        let range = lsp_types::Range {
+            start: lsp_types::Position {
+                line: 0,
+                character: 3,
+            },
+            end: lsp_types::Position {
+                line: 1,
+                character: 1,
+            },
+        };
+        assert_eq!(range_text(text, range).as_deref(), Some("b\r\nc"));
+        let past_end = PositionInfo { line: 0, column: 5 };
+        assert_eq!(checked_byte_offset(text, &past_end), None);
+    }
+
+    fn text_edit(start: (u32, u32), end: (u32, u32), new_text: &str) -> TextEditInfo {
+        let position = |(line, column)| PositionInfo { line, column };
+        TextEditInfo {
+            range: RangeInfo {
+                start: position(start),
+                end: position(end),
+            },
+            new_text: new_text.to_string(),
+        }
+    }
+
+    #[test]
+    fn text_edits_apply_in_any_order() {
+        let text = "let a = 1;\nlet b = 2;\n";
+        let edits = vec![
+            text_edit((1, 4), (1, 5), "y"),
+            text_edit((0, 4), (0, 5), "x"),
+            text_edit((2, 0), (2, 0), "let c = 3;\n"),
+        ];
+        assert_eq!(
+            apply_text_edits(text, edits).unwrap(),
+            "let x = 1;\nlet y = 2;\nlet c = 3;\n"
+        );
+    }
+
+    #[test]
+    fn invalid_text_edits_are_rejected() {
+        let text = "let a = 1;\nlet b = 2;\n";
+        let overlapping = vec![
+            text_edit((0, 0), (0, 6), "const"),
+            text_edit((1, 0), (1, 3), "var"),
+            text_edit((0, 4), (0, 9), "A = 2"),
+        ];
+        assert_eq!(
+            apply_text_edits(text, overlapping).unwrap_err(),
+            "edits 1 and 3 overlap"
+        );
+        assert_eq!(
+            apply_text_edits(text, vec![text_edit((1, 5), (0, 5), "")]).unwrap_err(),
+            "edit 1: range ends before it starts"
+        );
+        assert_eq!(
+            apply_text_edits(text, vec![text_edit((3, 0), (3, 0), "x")]).unwrap_err(),
+            "edit 1: position 3:0 is outside the file"
+        );
+    }
+
+    #[test]
+    fn snippets_expand_to_their_defaults() {
+        let expanded = |text: &str, placeholders: &[(u32, &str)]| {
+            let placeholders = placeholders
+                .iter()
+                .map(|(index, default)| (*index, default.to_string()))
+                .collect();
+            (text.to_string(), placeholders)
+        };
+
+        assert_eq!(expand_snippet("foo($1)"), expanded("foo()", &[(1, "")]));
+        assert_eq!(
+            expand_snippet("let ${2:value} = ${1:x};"),
+            expanded("let value = x;", &[(1, "x"), (2, "value")])
+        );
+        assert_eq!(
+            expand_snippet("${1:foo(${2:x})}"),
+            expanded("foo(x)", &[(1, "foo(x)"), (2, "x")])
+        );
+        assert_eq!(expand_snippet("${1|a,b|} c"), expanded("a c", &[(1, "a")]));
+        assert_eq!(
+            expand_snippet("\\$1 \\} \\\\ \\x"),
+            expanded("$1 } \\ \\x", &[])
+        );
+        assert_eq!(expand_snippet("x$0y"), expanded("xy", &[]));
+        assert_eq!(expand_snippet("${0:end}"), expanded("end", &[]));
+    }
+
+    #[test]
+    fn positions_are_displayed_one_based() {
+        let range = lsp_types::Range {
+            start: lsp_types::Position {
+                line: 2,
+                character: 4,
+            },
+            end: lsp_types::Position {
+                line: 2,
+                character: 9,
+            },
+        };
+        assert_eq!(display_position(lsp_types::Position::default()), "1:1");
+        assert_eq!(display_range(range), "3:5-3:10");
+    }
+
+    /// The text of a tool result.
+    fn text(result: CallToolResult) -> String {
+        result
+            .content
+            .iter()
+            .filter_map(|content| content.as_text().map(|text| text.text.clone()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[tokio::test]
+    async fn explain_symbol_sends_its_requests_together() {
+        let (server, mut mock) = mock_server(&[("src/lib.rs", "fn main() {}\n")], json!({})).await;
+        let request = ExplainSymbolRequest {
+            file_path: "src/lib.rs".to_string(),
+            line: 0,
+            column: 3,
+        };
+
+        // Nothing is answered until all three requests have arrived
+        let answer = async {
+            let mut requests = Vec::new();
+            while requests.len() < 3 {
+                let message = mock.receive().await;
+                if message.get("id").is_some() {
+                    requests.push(message);
+                }
+            }
+            for request in &requests {
+                let result = match request["method"].as_str() {
+                    Some("textDocument/hover") => json!({ "contents": "fn main()" }),
+                    _ => Value::Null,
+                };
+                mock.respond(request, result).await;
+            }
+        };
+        let (result, ()) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(server.explain_symbol(Parameters(request)), answer)
+        })
+        .await
+        .expect("explain_symbol waited for one answer before sending the next request");
+
+        let text = text(result.unwrap());
+        assert!(text.contains("fn main()"), "{}", text);
+        assert!(text.contains("No definition found"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn server_errors_reach_the_client_unchanged() {
+        let (server, mut mock) = mock_server(&[("src/lib.rs", "fn main() {}\n")], json!({})).await;
+        let request = HoverRequest {
+            file_path: "src/lib.rs".to_string(),
+            line: 0,
+            column: 3,
+            format: DocFormat::Markdown,
+            concise: false,
+            raw: false,
+        };
+
+        let answer = async {
+            let request = mock.expect("textDocument/hover").await;
+            let error = json!({
+                "code": -32602,
+                "message": "invalid offset",
+                "data": { "offset": 3 }
+            });
+            mock.reply(&request, json!({ "error": error })).await;
+        };
+        let (result, ()) = tokio::join!(server.hover(Parameters(request)), answer);
+
+        let error = result.unwrap_err();
+        assert_eq!(error.code, ErrorCode(-32602));
+        assert_eq!(
+            error.message,
+            "LSP error: textDocument/hover failed: invalid offset"
+        );
+        assert_eq!(error.data, Some(json!({ "offset": 3 })));
+    }
+
+    #[tokio::test]
+    async fn completion_after_a_dot_sends_the_trigger_character() {
+        let source = "struct User { name: String, age: u32 }\nfn greet(user: User) { user. }\n";
+        let (server, mut mock) = mock_server(&[("src/lib.rs", source)], json!({})).await;
+        let request = CompletionRequest {
+            file_path: "src/lib.rs".to_string(),
+            line: 1,
+            column: 28,
+            format: DocFormat::Markdown,
+            trigger_kind: None,
+            trigger_character: Some(".".to_string()),
+            filter_prefix: false,
+            raw_order: false,
+        };
+
+        let answer = async {
+            let request = mock.expect("textDocument/completion").await;
+            let items = json!([
+                { "label": "name", "kind": 5, "detail": "String" },
+                { "label": "age", "kind": 5, "detail": "u32" }
+            ]);
+            mock.respond(&request, items).await;
+            request
+        };
+        let (result, sent) = tokio::join!(server.completion(Parameters(request)), answer);
+
+        assert_eq!(
+            sent["params"]["context"],
+            json!({ "triggerKind": 2, "triggerCharacter": "." })
+        );
+        assert_eq!(
+            sent["params"]["position"],
+            json!({ "line": 1, "character": 28 })
+        );
+        let text = text(result.unwrap());
+        assert!(text.contains("[Field] name"), "{}", text);
+        assert!(text.contains("[Field] age"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn code_actions_offer_quick_fixes_without_a_diagnostics_call() {
+        let source = "fn main() {\n    let x: i32 = \"one\";\n}\n";
+        let (server, mut mock) = mock_server(&[("src/main.rs", source)], json!({})).await;
+        let request = CodeActionsRequest {
+            file_path: "src/main.rs".to_string(),
+            line: 1,
+            column: 18,
+            only: None,
+        };
+
+        let diagnostic = json!({
+            "range": {
+                "start": { "line": 1, "character": 17 },
+                "end": { "line": 1, "character": 22 }
+            },
+            "severity": 1,
+            "code": "E0308",
+            "source": "rustc",
+            "message": "mismatched types"
+        });
+        let answer = async {
+            let request = mock.expect("textDocument/diagnostic").await;
+            let report = json!({ "kind": "full", "items": [diagnostic] });
+            mock.respond(&request, report).await;
+
+            let request = mock.expect("textDocument/codeAction").await;
+            let fix = json!({
+                "title": "Change the type of `x` to `&str`",
+                "kind": "quickfix",
+                "diagnostics": [diagnostic]
+            });
+            mock.respond(&request, json!([fix])).await;
+            request
+        };
+        let (result, sent) = tokio::join!(server.code_actions(Parameters(request)), answer);
+
+        assert_eq!(
+            sent["params"]["context"]["diagnostics"],
+            json!([diagnostic])
+        );
+        let text = text(result.unwrap());
+        assert!(
+            text.contains("1. Change the type of `x` to `&str` (quickfix) [Fixes 1 diagnostic(s)]"),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn idle_shutdown_keeps_settings_and_forgets_listed_items() {
+        let (server, mut mock) = mock_server(&[], json!({})).await;
+        let lsp_client = server.client().await.unwrap();
+        lsp_client
+            .configure(json!({ "checkOnSave": false }))
+            .await
+            .unwrap();
+        drop(lsp_client);
+        let session = server.session();
+        for server in [&server, &session] {
+            server.listed_items.lock().await.completions.push(
+                lsp_types::CompletionItem::new_simple("name".to_string(), "String".to_string()),
+            );
+        }
+
+        server.spawn_idle_watcher(Duration::from_millis(50));
+        let request = mock.expect("shutdown").await;
+        mock.respond(&request, Value::Null).await;
+        mock.expect("exit").await;
+
+        assert!(server.lsp_client.lock().await.is_none());
+        assert!(server.listed_items.lock().await.completions.is_empty());
+        assert!(session.listed_items.lock().await.completions.is_empty());
+        let settings = server.stopped_settings.lock().await.clone().unwrap();
+        assert_eq!(settings["checkOnSave"], json!(false));
+        assert_eq!(settings["cargo"]["runBuildScripts"], json!(true));
+    }
+
+    #[tokio::test]
+    async fn ping_reports_indexing_while_work_is_in_progress() {
+        let (server, mut mock) = mock_server(&[], json!({})).await;
+        let ping = || server.ping(Parameters(PingRequest { timeout_ms: 1000 }));
+        let progress = |value: Value| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "$/progress",
+                "params": { "token": "indexing", "value": value }
+            })
+        };
+
+        mock.send(progress(
+            json!({ "kind": "begin", "title": "Indexing", "percentage": 10 }),
+        ))
+        .await;
+        let answer = async {
+            let request = mock.expect("$/ping").await;
+            mock.respond(&request, Value::Null).await;
+        };
+        let (result, ()) = tokio::join!(ping(), answer);
+        let status = text(result.unwrap());
+        assert!(
+            status.ends_with("Status: indexing (Indexing (10%))"),
+            "{}",
+            status
+        );
+
+        mock.send(progress(json!({ "kind": "end" }))).await;
+        let answer = async {
+            let request = mock.expect("$/ping").await;
+            mock.respond(&request, Value::Null).await;
+        };
+        let (result, ()) = tokio::join!(ping(), answer);
+        let status = text(result.unwrap());
+        assert!(status.ends_with("Status: ready"), "{}", status);
+    }
+
+    #[tokio::test]
+    async fn definition_source_stops_at_the_end_of_the_file() {
+        let source = "use a;\nuse b;\nstatic X: u8 = 0;\n";
+        let (server, mut mock) = mock_server(&[("src/lib.rs", source)], json!({})).await;
+        let uri =
+            lsp_types::Url::from_file_path(server.workspace_roots[0].join("src/lib.rs")).unwrap();
+        let request = DefinitionSourceRequest {
+            file_path: "src/lib.rs".to_string(),
+            line: 2,
+            column: 7,
+        };
+
+        let answer = async {
+            let request = mock.expect("textDocument/definition").await;
+            let location = json!({
+                "uri": uri,
+                "range": {
+                    "start": { "line": 2, "character": 7 },
+                    "end": { "line": 2, "character": 8 }
+                }
+            });
+            mock.respond(&request, location).await;
+            let request = mock.expect("textDocument/documentSymbol").await;
+            mock.respond(&request, json!([])).await;
+        };
+        let (result, ()) = tokio::join!(server.definition_source(Parameters(request)), answer);
+
+        let text = text(result.unwrap());
+        assert!(text.contains(":1:1-4:1 (not inside an item"), "{}", text);
+        assert!(text.ends_with("static X: u8 = 0;\n```"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn inlay_hint_ranges_stay_in_order_and_in_the_file() {
+        let source = "fn a() {}\nfn b() {}\n";
+        let (server, mut mock) = mock_server(&[("src/lib.rs", source)], json!({})).await;
+        let request = |start_line, end_line| InlayHintsRequest {
+            file_path: "src/lib.rs".to_string(),
+            start_line: Some(start_line),
+            end_line: Some(end_line),
+        };
+
+        let inverted = server.inlay_hints(Parameters(request(1, 0))).await;
+        assert_eq!(
+            inverted.unwrap_err().message,
+            "start_line 1 is after end_line 0"
+        );
+
+        let answer = async {
+            let request = mock.expect("textDocument/inlayHint").await;
+            mock.respond(&request, json!([])).await;
+            request
+        };
+        let (result, sent) = tokio::join!(server.inlay_hints(Parameters(request(0, 100))), answer);
+        result.unwrap();
+        assert_eq!(
+            sent["params"]["range"],
+            json!({
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 1, "character": 9 }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_number_their_own_items() {
+        let (first, mut mock) = mock_server(&[("src/lib.rs", "fn main() {}\n")], json!({})).await;
+        let second = first.session();
+
+        for (server, label) in [(&first, "first_item"), (&second, "second_item")] {
+            let request = CompletionRequest {
+                file_path: "src/lib.rs".to_string(),
+                line: 0,
+                column: 0,
+                format: DocFormat::Markdown,
+                trigger_kind: None,
+                trigger_character: None,
+                filter_prefix: false,
+                raw_order: false,
+            };
+            let answer = async {
+                let request = mock.expect("textDocument/completion").await;
+                mock.respond(&request, json!([{ "label": label }])).await;
+            };
+            let (result, ()) = tokio::join!(server.completion(Parameters(request)), answer);
+            result.unwrap();
+        }
+
+        let request = ResolveCompletionRequest {
+            index: 1,
+            format: DocFormat::Markdown,
+        };
+        let resolved = text(first.resolve_completion(Parameters(request)).await.unwrap());
+        assert!(resolved.starts_with("first_item"), "{}", resolved);
+    }
+
+    #[tokio::test]
+    async fn documentation_follows_the_requested_format() {
+        let (server, mut mock) = mock_server(&[("src/lib.rs", "fn main() {}\n")], json!({})).await;
+        let markdown = |value: &str| json!({ "kind": "markdown", "value": value });
+
+        let request = SignatureHelpRequest {
+            file_path: "src/lib.rs".to_string(),
+            line: 0,
+            column: 0,
+            trigger_character: None,
+            is_retrigger: None,
+            format: DocFormat::Plaintext,
+        };
+        let answer = async {
+            let request = mock.expect("textDocument/signatureHelp").await;
+            let help = json!({
+                "signatures": [{
+                    "label": "fn add(a: u32)",
+                    "documentation": markdown("Adds **one** number"),
+                    "parameters": [{ "label": "a", "documentation": markdown("The `a` value") }]
+                }]
+            });
+            mock.respond(&request, help).await;
+        };
+        let (result, ()) = tokio::join!(server.signature_help(Parameters(request)), answer);
+        let help = text(result.unwrap());
+        assert!(help.contains("\n   Adds one number\n"), "{}", help);
+        assert!(help.contains("a - The a value"), "{}", help);
+
+        let request = CompletionRequest {
+            file_path: "src/lib.rs".to_string(),
+            line: 0,
+            column: 0,
+            format: DocFormat::Markdown,
+            trigger_kind: None,
+            trigger_character: None,
+            filter_prefix: false,
+            raw_order: false,
+        };
+        let answer = async {
+            let request = mock.expect("textDocument/completion").await;
+            let item = json!({ "label": "add", "documentation": markdown("# Adds\n*one*") });
+            mock.respond(&request, json!([item])).await;
+        };
+        let (result, ()) = tokio::join!(server.completion(Parameters(request)), answer);
+        result.unwrap();
+
+        let request = ResolveCompletionRequest {
+            index: 1,
+            format: DocFormat::Plaintext,
+        };
+        let resolved = server.resolve_completion(Parameters(request)).await;
+        let resolved = text(resolved.unwrap());
+        assert!(resolved.contains("Adds\none"), "{}", resolved);
+    }
+
+    #[tokio::test]
+    async fn rename_lists_edits_one_based() {
+        let source = "fn old() {}\nfn main() { old(); }\n";
+        let (server, mut mock) = mock_server(&[("src/lib.rs", source)], json!({})).await;
+        let request = RenameRequest {
+            file_path: "src/lib.rs".to_string(),
+            line: 0,
+            column: 3,
+            new_name: "new".to_string(),
+            diff: false,
+        };
+
+        let answer = async {
+            let request = mock.expect("textDocument/rename").await;
+            let uri = request["params"]["textDocument"]["uri"].as_str().unwrap();
+            let edit = |line: u32, start: u32| {
+                json!({
+                    "range": {
+                        "start": { "line": line, "character": start },
+                        "end": { "line": line, "character": start + 3 }
+                    },
+                    "newText": "new"
+                })
+            };
+            let changes = json!({ "changes": { uri: [edit(0, 3), edit(1, 12)] } });
+            mock.respond(&request, changes).await;
+        };
+        let (result, ()) = tokio::join!(server.rename(Parameters(request)), answer);
+
+        let text = text(result.unwrap());
+        assert!(text.contains("  - 1:4-1:7: Replace with 'new'"), "{}", text);
+        assert!(
+            text.contains("  - 2:13-2:16: Replace with 'new'"),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn code_lenses_show_one_based_ranges() {
+        let source = "#[test]\nfn works() {}\n";
+        let (server, mut mock) = mock_server(&[("src/lib.rs", source)], json!({})).await;
+        let request = CodeLensesRequest {
+            file_path: "src/lib.rs".to_string(),
+        };
+
+        let answer = async {
+            let request = mock.expect("textDocument/codeLens").await;
+            let lens = json!({
+                "range": {
+                    "start": { "line": 1, "character": 3 },
+                    "end": { "line": 1, "character": 8 }
+                },
+                "command": { "title": "Run Test", "command": "rust-analyzer.runSingle" }
+            });
+            mock.respond(&request, json!([lens])).await;
+        };
+        let (result, ()) = tokio::join!(server.code_lenses(Parameters(request)), answer);
+
+        let text = text(result.unwrap());
+        assert!(
+            text.contains("2:4-2:9: Run Test (command: rust-analyzer.runSingle)"),
+            "{}",
+            text
+        );
+    }
+}
@@ -0,0 +1,278 @@
+/// Convert LSP markdown (hover contents, completion docs) to plain text.
+///
+/// Code fences are dropped but their contents kept, heading markers and
+/// horizontal rules are removed, links are reduced to their text, and inline
+/// code / emphasis markers are stripped.
+pub fn to_plaintext(markdown: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_fence = false;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+
+        if in_fence {
+            lines.push(line.to_string());
+            continue;
+        }
+
+        if is_horizontal_rule(trimmed) {
+            lines.push(String::new());
+            continue;
+        }
+
+        lines.push(strip_inline(strip_heading(trimmed)));
+    }
+
+    lines.dedup_by(|a, b| a.trim().is_empty() && b.trim().is_empty());
+    lines.join("\n").trim().to_string()
+}
+
+/// The main code block of a hover: the last fenced block before the first
+/// horizontal rule. rust-analyzer lists the item's path first and its type or
+/// signature last, with the documentation after the rule.
+pub fn primary_code_block(markdown: &str) -> Option<String> {
+    header_code_blocks(markdown).pop()
+}
+
+/// The non-empty fenced blocks of a hover before its first horizontal rule,
+/// in order.
+pub fn header_code_blocks(markdown: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            match current.take() {
+                Some(block) => blocks.push(block.join("\n")),
+                None => current = Some(Vec::new()),
+            }
+            continue;
+        }
+
+        match current.as_mut() {
+            Some(block) => block.push(line),
+            None if is_horizontal_rule(trimmed) => break,
+            None => {}
+        }
+    }
+
+    blocks
+        .into_iter()
+        .map(|block| block.trim().to_string())
+        .filter(|block| !block.is_empty())
+        .collect()
+}
+
+fn strip_heading(line: &str) -> &str {
+    let without_hashes = line.trim_start_matches('#');
+    let level = line.len() - without_hashes.len();
+    if (1..=6).contains(&level) && (without_hashes.is_empty() || without_hashes.starts_with(' ')) {
+        without_hashes.trim()
+    } else {
+        line
+    }
+}
+
+fn is_horizontal_rule(line: &str) -> bool {
+    let line = line.trim_end();
+    match line.chars().next() {
+        Some(marker @ ('-' | '*' | '_')) => line.len() >= 3 && line.chars().all(|c| c == marker),
+        _ => false,
+    }
+}
+
+fn strip_inline(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let emphasis = emphasis_markers(&chars);
+    let mut result = String::with_capacity(line.len());
+    let mut in_code = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '`' {
+            in_code = !in_code;
+            i += 1;
+            continue;
+        }
+
+        if in_code {
+            result.push(c);
+            i += 1;
+            continue;
+        }
+
+        match c {
+            '\\' if i + 1 < chars.len() && chars[i + 1].is_ascii_punctuation() => {
+                result.push(chars[i + 1]);
+                i += 2;
+            }
+            '*' | '_' if emphasis[i] => {
+                i += 1;
+            }
+            '[' => match parse_link(&chars, i) {
+                Some((text, next)) => {
+                    result.push_str(&strip_inline(&text));
+                    i = next;
+                }
+                None => {
+                    result.push(c);
+                    i += 1;
+                }
+            },
+            _ => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    result
+}
+
+/// Which of `chars` are emphasis markers (`*`, `**`, `_`, `__`) closed by a
+/// matching run, outside code spans and links. Unmatched runs are literal text.
+fn emphasis_markers(chars: &[char]) -> Vec<bool> {
+    let mut markers = vec![false; chars.len()];
+    // Runs that may still be closed, as (start, length, marker)
+    let mut openers: Vec<(usize, usize, char)> = Vec::new();
+    let mut in_code = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '`' => {
+                in_code = !in_code;
+                i += 1;
+            }
+            _ if in_code => i += 1,
+            '\\' => i += 2,
+            // Link text is stripped on its own
+            '[' => i = parse_link(chars, i).map_or(i + 1, |(_, next)| next),
+            '*' | '_' => {
+                let len = chars[i..].iter().take_while(|&&d| d == c).count();
+                let before = i.checked_sub(1).map(|j| chars[j]);
+                let (can_open, can_close) = flanking(c, before, chars.get(i + len).copied());
+                let opener = openers
+                    .iter()
+                    .rposition(|&(_, l, d)| d == c && l == len)
+                    .filter(|_| can_close);
+                match opener {
+                    Some(index) => {
+                        let start = openers[index].0;
+                        openers.truncate(index);
+                        markers[start..start + len].fill(true);
+                        markers[i..i + len].fill(true);
+                    }
+                    None if can_open => openers.push((i, len, c)),
+                    None => {}
+                }
+                i += len;
+            }
+            _ => i += 1,
+        }
+    }
+
+    markers
+}
+
+/// Whether a run of `marker` between `before` and `after` can open and close
+/// emphasis, by CommonMark's flanking rules. `_` does neither inside a word,
+/// so `snake_case` and `foo__bar` keep their underscores.
+fn flanking(marker: char, before: Option<char>, after: Option<char>) -> (bool, bool) {
+    let is_space = |c: Option<char>| c.is_none_or(char::is_whitespace);
+    let is_punct = |c: Option<char>| c.is_some_and(|c| c.is_ascii_punctuation());
+    let left = !is_space(after) && (!is_punct(after) || is_space(before) || is_punct(before));
+    let right = !is_space(before) && (!is_punct(before) || is_space(after) || is_punct(after));
+    if marker == '_' {
+        (
+            left && (!right || is_punct(before)),
+            right && (!left || is_punct(after)),
+        )
+    } else {
+        (left, right)
+    }
+}
+
+/// Parse `[text](target)` starting at `start`, returning the link text and
+/// the index just past the closing parenthesis.
+fn parse_link(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let mut depth = 0;
+    let mut text_end = None;
+    for (offset, &c) in chars[start..].iter().enumerate() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    text_end = Some(start + offset);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let text_end = text_end?;
+
+    if chars.get(text_end + 1) != Some(&'(') {
+        return None;
+    }
+    let target_end = chars[text_end + 2..].iter().position(|&c| c == ')')? + text_end + 2;
+
+    let text = chars[start + 1..text_end].iter().collect();
+    Some((text, target_end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fences_headings_and_rules() {
+        let markdown = "# Title\n```rust\nfn main() {}\n```\n---\nRuns it.";
+        assert_eq!(to_plaintext(markdown), "Title\nfn main() {}\n\nRuns it.");
+    }
+
+    #[test]
+    fn links_keep_their_text() {
+        let markdown =
+            "See [`Vec`](https://doc.rust-lang.org/std/vec/struct.Vec.html) and [docs][1].";
+        assert_eq!(to_plaintext(markdown), "See Vec and [docs][1].");
+    }
+
+    #[test]
+    fn emphasis_markers_are_stripped() {
+        assert_eq!(
+            to_plaintext("**Bold**, *italic*, _also_ and __strong__"),
+            "Bold, italic, also and strong"
+        );
+        assert_eq!(
+            to_plaintext("***both*** and **Note:** this"),
+            "both and Note: this"
+        );
+    }
+
+    #[test]
+    fn unmatched_and_intraword_markers_are_kept() {
+        let text = "Call __private or foo__bar with snake_case_name";
+        assert_eq!(to_plaintext(text), text);
+        assert_eq!(to_plaintext("2 * 3 * 4"), "2 * 3 * 4");
+    }
+
+    #[test]
+    fn code_spans_and_escapes_are_literal() {
+        assert_eq!(
+            to_plaintext("Use `a*b*c` or \\*literal\\*"),
+            "Use a*b*c or *literal*"
+        );
+    }
+}
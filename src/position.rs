@@ -0,0 +1,79 @@
+//! Conversions between byte offsets and LSP positions, whose columns count
+//! UTF-16 code units. Line breaks are `\n` or `\r\n`; neither is part of a
+//! line's columns.
+
+use lsp_types::Position;
+
+/// Byte offset of UTF-16 `column` in `line`, or `None` if the line is
+/// shorter. A column inside a surrogate pair rounds up to the next character.
+pub fn column_offset(line: &str, column: u32) -> Option<usize> {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= column {
+            return Some(i);
+        }
+        units += c.len_utf16() as u32;
+    }
+    (units >= column).then_some(line.len())
+}
+
+/// Byte offset of `position` in `text`, or `None` if its line or column is past
+/// the end.
+pub fn offset_at(text: &str, position: Position) -> Option<usize> {
+    let mut lines = text.split_inclusive('\n');
+    let mut offset = 0;
+    for _ in 0..position.line {
+        offset += lines.next()?.len();
+    }
+    let line = text[offset..].lines().next().unwrap_or_default();
+    column_offset(line, position.character).map(|column| offset + column)
+}
+
+/// The position of byte `offset` in `text`.
+pub fn position_at(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    #[test]
+    fn columns_count_utf16_units() {
+        // é is one unit in two bytes, the emoji two units in four bytes
+        let line = "é😀x";
+        assert_eq!(column_offset(line, 0), Some(0));
+        assert_eq!(column_offset(line, 1), Some(2));
+        assert_eq!(column_offset(line, 2), Some(6));
+        assert_eq!(column_offset(line, 3), Some(6));
+        assert_eq!(column_offset(line, 4), Some(7));
+        assert_eq!(column_offset(line, 5), None);
+    }
+
+    #[test]
+    fn offsets_skip_line_breaks() {
+        let text = "ab\r\né😀x\nlast";
+        assert_eq!(offset_at(text, position(0, 2)), Some(2));
+        assert_eq!(offset_at(text, position(0, 3)), None);
+        assert_eq!(offset_at(text, position(1, 3)), Some(10));
+        assert_eq!(offset_at(text, position(2, 4)), Some(16));
+        assert_eq!(offset_at(text, position(4, 0)), None);
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let text = "ab\r\né😀x\nlast";
+        for (offset, _) in text.char_indices().filter(|&(i, _)| i != 3) {
+            assert_eq!(offset_at(text, position_at(text, offset)), Some(offset));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use rmcp::{model::*, ErrorData as McpError};
+
+/// Prompts offered to clients as guided entry points into the tools.
+pub fn list() -> Vec<Prompt> {
+    vec![
+        Prompt::new(
+            "explain_symbol",
+            Some("Explain the symbol at a position using hover, definition and references"),
+            Some(vec![
+                argument("file_path", "Path of the file containing the symbol", true),
+                argument("line", "0-indexed line of the symbol", true),
+                argument("column", "0-indexed column of the symbol", true),
+            ]),
+        ),
+        Prompt::new(
+            "triage_diagnostics",
+            Some("Fetch a file's diagnostics, group them and suggest fixes"),
+            Some(vec![argument(
+                "file_path",
+                "Path of the file to triage",
+                true,
+            )]),
+        ),
+    ]
+}
+
+/// Render the prompt `name` with the client-supplied `arguments`.
+pub fn get(name: &str, arguments: Option<JsonObject>) -> Result<GetPromptResult, McpError> {
+    let arguments = arguments.unwrap_or_default();
+
+    let (description, text) = match name {
+        "explain_symbol" => {
+            let file_path = required(&arguments, "file_path")?;
+            let line = required(&arguments, "line")?;
+            let column = required(&arguments, "column")?;
+            (
+                "Explain a symbol",
+                format!(
+                    "Explain the symbol at line {line}, column {column} (0-indexed) of {file_path}.\n\n\
+                     1. Call 'hover' at that position for its type and documentation.\n\
+                     2. Call 'goto_definition' at that position and read the definition.\n\
+                     3. Call 'find_references' at that position to see how it is used.\n\n\
+                     Summarize what the symbol is, what it does and where it is used, \
+                     citing file:line locations from the tool results."
+                ),
+            )
+        }
+        "triage_diagnostics" => {
+            let file_path = required(&arguments, "file_path")?;
+            (
+                "Triage diagnostics",
+                format!(
+                    "Triage the diagnostics in {file_path}.\n\n\
+                     1. Call 'diagnostics' for the file.\n\
+                     2. Group the results by severity (errors first, then warnings), \
+                     merging diagnostics that share a root cause.\n\
+                     3. For each error, call 'code_actions' at its position and note any quick fix.\n\n\
+                     Report the groups in order of importance with a suggested fix for each."
+                ),
+            )
+        }
+        _ => {
+            return Err(McpError::invalid_params(
+                format!("Unknown prompt: {}", name),
+                None,
+            ))
+        }
+    };
+
+    Ok(GetPromptResult {
+        description: Some(description.to_string()),
+        messages: vec![PromptMessage::new_text(PromptMessageRole::User, text)],
+    })
+}
+
+fn argument(name: &str, description: &str, required: bool) -> PromptArgument {
+    PromptArgument {
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        required: Some(required),
+    }
+}
+
+fn required(arguments: &JsonObject, name: &str) -> Result<String, McpError> {
+    match arguments.get(name) {
+        Some(serde_json::Value::String(value)) => Ok(value.clone()),
+        Some(value) => Ok(value.to_string()),
+        None => Err(McpError::invalid_params(
+            format!("Missing prompt argument: {}", name),
+            None,
+        )),
+    }
+}
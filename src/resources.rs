@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+
+/// Collect the workspace files exposed as MCP resources: `.rs` sources and
+/// `Cargo.toml` manifests. `.gitignore` rules and hidden files are respected,
+/// and `target/` directories are always skipped.
+pub fn workspace_files(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    for root in roots {
+        let walker = ignore::WalkBuilder::new(root)
+            .filter_entry(|entry| entry.file_name() != "target")
+            .build();
+
+        for entry in walker.flatten() {
+            let path = entry.path();
+            if path.is_file() && is_resource_file(path) {
+                files.push(path.to_path_buf());
+            }
+        }
+    }
+
+    files.sort();
+    files.dedup();
+    files
+}
+
+pub fn is_resource_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "rs")
+        || path.file_name().is_some_and(|name| name == "Cargo.toml")
+}
+
+pub fn mime_type(path: &Path) -> &'static str {
+    if path.extension().is_some_and(|ext| ext == "rs") {
+        "text/x-rust"
+    } else {
+        "application/toml"
+    }
+}
+
+/// Path of `file` relative to the workspace root containing it, for display.
+pub fn display_name(roots: &[PathBuf], file: &Path) -> String {
+    roots
+        .iter()
+        .find_map(|root| file.strip_prefix(root).ok())
+        .unwrap_or(file)
+        .to_string_lossy()
+        .into_owned()
+}